@@ -1,8 +1,334 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    process
+};
+
+use libc;
+use toml;
+
+use crate::iface::{ErrorMessage, TerminalOptions, ErrorOptions, RightOverflow, PromptLayout};
+
 pub const PATH_TOP_ENV_VAR: &str = "__PS_PATH_TOP";
 pub const FALLBACK_COLUMN_COUNT: usize = 50;
 pub const PROMPT_ENDING: &str = "☛ ";
 pub const MIN_COLUMN_COUNT: usize = 10;
+pub const CONFIG_PATH_ENV_VAR: &str = "PROMPTY_CONFIG";
+pub const DEFAULT_SEGMENTS: &[&str] = &["cwd", "git"];
 
 
 //-- config for plugin_impl
-pub const MAX_JOIN_PADDING: usize = 10;
\ No newline at end of file
+pub const MAX_JOIN_PADDING: usize = 10;
+/// Columns which have to be left for the command line by the compact layout.
+pub const COMPACT_MIN_INPUT_SPACE: usize = 20;
+
+
+/// Options of a single segment as given in the `[segment.<name>]` table.
+pub type SegmentOptions = toml::value::Table;
+
+/// The user config, normally loaded from `$XDG_CONFIG_HOME/prompty/config.toml`.
+///
+/// ```toml
+/// segments = ["cwd", "git", "exit_status"]
+///
+/// [segment.exit_status]
+/// show_success = true
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Names of the segments to render, in order.
+    pub segments: Vec<String>,
+    /// Names of the segments to render right aligned on the first prompt line, in order.
+    pub right_segments: Vec<String>,
+    /// Names of the segments to render in the right prompt of shells which have one (zsh).
+    pub rprompt_segments: Vec<String>,
+    /// Per-segment options, keyed by segment name.
+    ///
+    /// The order of the tables in the config file is not kept, as only the
+    /// segment lists above decide which segments are rendered in which order.
+    pub segment: BTreeMap<String, SegmentOptions>,
+    /// Options for the git plugin.
+    pub git: GitConfig,
+    /// Options for the jump shell function.
+    pub jump: JumpConfig,
+    /// Options for the terminal plugin.
+    pub terminal: TerminalConfig
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            segments: DEFAULT_SEGMENTS.iter().map(|name| name.to_string()).collect(),
+            right_segments: Vec::new(),
+            rprompt_segments: Vec::new(),
+            segment: Default::default(),
+            git: Default::default(),
+            jump: Default::default(),
+            terminal: Default::default()
+        }
+    }
+}
+
+/// The `[git]` table of the config.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitConfig {
+    /// Time after which `git status` is aborted.
+    pub timeout_ms: u64
+}
+
+impl Default for GitConfig {
+    fn default() -> Self {
+        GitConfig { timeout_ms: 2000 }
+    }
+}
+
+/// The `[jump]` table of the config.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JumpConfig {
+    /// Name of the shell function emitted by `--bash-setup`.
+    pub function_name: String
+}
+
+impl Default for JumpConfig {
+    fn default() -> Self {
+        JumpConfig { function_name: "g".to_owned() }
+    }
+}
+
+/// The `[terminal]` table of the config.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TerminalConfig {
+    /// Report the cwd to the terminal emulator using OSC 7, so that new tabs
+    /// can be opened in the same directory.
+    pub osc7: bool,
+    /// Mark the prompt using OSC 133 (FinalTerm) sequences, so that terminals can
+    /// jump between prompts and select the output of commands.
+    pub semantic_prompt: bool,
+    /// Use OSC 8 hyperlinks, if not set they are used if the terminal is known to support them.
+    pub hyperlinks: Option<bool>,
+    /// Replace the prompt with a minimal one line form once a command was entered.
+    pub transient_prompt: bool,
+    /// Also set the prompt of the `select` shell command (`PS3`).
+    pub select_prompt: bool,
+    /// Also set the prompt of `set -x` traces (`PS4`) to show the file and line.
+    pub xtrace_prompt: bool,
+    /// What to do with the right aligned segments if they do not fit on the first line.
+    pub right_overflow: RightOverflow,
+    /// Max number of lines used for segments, if they need more the ones with
+    /// the lowest priority are dropped.
+    ///
+    /// Lines of errors are not counted. Right aligned segments which would need
+    /// a line beyond the limit are dropped.
+    pub max_lines: Option<usize>,
+    /// How the segments are laid out, only used by the box style.
+    pub layout: PromptLayout,
+    /// How the prompt looks.
+    pub style: TerminalStyle,
+    /// Use only ASCII separators in the powerline style, if not set they are
+    /// used if the locale isn't UTF-8.
+    pub ascii: Option<bool>,
+    /// How errors are shown.
+    pub errors: ErrorOptions
+}
+
+impl TerminalConfig {
+    /// The options used by the terminal plugin.
+    pub fn options(&self) -> TerminalOptions {
+        TerminalOptions {
+            osc7: self.osc7,
+            semantic_prompt: self.semantic_prompt,
+            hyperlinks: self.hyperlinks,
+            right_overflow: self.right_overflow,
+            max_lines: self.max_lines,
+            layout: self.layout,
+            ascii: self.ascii,
+            errors: self.errors.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TerminalStyle {
+    /// Segments are drawn in a box using box drawing chars.
+    #[default]
+    Box,
+    /// Segments have a background color and are separated by arrows.
+    Powerline
+}
+
+impl Config {
+
+    /// Loads the config from the config file.
+    ///
+    /// If there is no config file the default config is returned.
+    pub fn load() -> Result<Config, ErrorMessage> {
+        let path =
+            match config_file_path() {
+                Some(path) => path,
+                None => return Ok(Config::default())
+            };
+
+        let content =
+            match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                    return Ok(Config::default());
+                },
+                Err(err) => {
+                    let msg = format!("can not read {}: {}", path.display(), err);
+                    return Err(ErrorMessage::new(msg));
+                }
+            };
+
+        Config::parse(&content)
+            .map_err(|err| ErrorMessage::new(format!("{}: {}", path.display(), err.msg())))
+    }
+
+    /// Like [`Config::load`] but falls back to the default config on error.
+    pub fn load_or_default() -> (Config, Option<ErrorMessage>) {
+        match Config::load() {
+            Ok(config) => (config, None),
+            Err(err) => (Config::default(), Some(err))
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Config, ErrorMessage> {
+        toml::from_str(content)
+            .map_err(|err| ErrorMessage::new(format!("{}", err)))
+    }
+
+    /// Returns the options for given segment, which are empty if none where given.
+    pub fn segment_options(&self, name: &str) -> SegmentOptions {
+        self.segment.get(name).cloned().unwrap_or_default()
+    }
+}
+
+fn config_file_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(CONFIG_PATH_ENV_VAR) {
+        return Some(PathBuf::from(path));
+    }
+
+    let config_dir =
+        match env::var_os("XDG_CONFIG_HOME") {
+            Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config")
+        };
+
+    Some(config_dir.join("prompty").join("config.toml"))
+}
+
+/// Directory for files which only need to live as long as the login session, e.g. caches.
+///
+/// Without `$XDG_RUNTIME_DIR` it's in the shared temp dir, where anyone could have
+/// created it first, so it's created private to the user and only used if it still is.
+pub fn runtime_dir() -> io::Result<PathBuf> {
+    let dir =
+        match env::var_os("XDG_RUNTIME_DIR") {
+            Some(ref dir) if !dir.is_empty() => PathBuf::from(dir).join("prompty"),
+            _ => {
+                let user = env::var("USER").unwrap_or_default();
+                env::temp_dir().join(format!("prompty-{}", user))
+            }
+        };
+
+    private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Creates the directory if needed and checks that it's a directory only the user can access.
+fn private_dir(dir: &Path) -> io::Result<()> {
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {},
+        res => res?
+    }
+
+    // not following symlinks, as they could point anywhere
+    let meta = fs::symlink_metadata(dir)?;
+    let uid = unsafe { libc::getuid() };
+    if !meta.file_type().is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not a directory private to the user", dir.display())
+        ));
+    }
+    Ok(())
+}
+
+/// Creates the sub directory of the [`runtime_dir`] with given name.
+pub fn runtime_sub_dir(name: &str) -> io::Result<PathBuf> {
+    let dir = runtime_dir()?.join(name);
+    private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Writes to a new temporary file next to the path and then renames it, so that
+/// neither half written files are read nor existing files or symlinks are written through.
+pub fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(format!(".{}.tmp", process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let res = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .and_then(|mut file| file.write_all(content))
+        .and_then(|()| fs::rename(&tmp_path, path));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    res
+}
+
+/// Directory for persistent data, e.g. the bookmarks of `prompty jump`.
+pub fn data_dir() -> Option<PathBuf> {
+    let data_dir =
+        match env::var_os("XDG_DATA_HOME") {
+            Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("share")
+        };
+
+    Some(data_dir.join("prompty"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    #[test]
+    fn only_private_dirs_are_used_and_written_files_are_replaced() {
+        let tmp = env::temp_dir().join(format!("prompty-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&tmp);
+        private_dir(&tmp).unwrap();
+        assert_eq!(fs::metadata(&tmp).unwrap().mode() & 0o777, 0o700);
+
+        let shared = tmp.join("shared");
+        fs::create_dir(&shared).unwrap();
+        fs::set_permissions(&shared, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(private_dir(&shared).is_err());
+        let link = tmp.join("link");
+        symlink(&tmp, &link).unwrap();
+        assert!(private_dir(&link).is_err());
+
+        let target = tmp.join("target");
+        fs::write(&target, "target").unwrap();
+        let file = tmp.join("file");
+        symlink(&target, &file).unwrap();
+        write_atomically(&file, b"content").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "content");
+        assert_eq!(fs::read_to_string(&target).unwrap(), "target");
+
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+//! The errors of the plugins.
+//!
+//! Each plugin has an error kind of its own, which lets the terminal decide
+//! how to show an error, e.g. how severe it is and which hint to give.
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt,
+    io,
+    path::PathBuf,
+    time::Duration
+};
+
+use crate::iface::ErrorMessage;
+
+/// How severe an error is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Likely to go away on its own, e.g. a timeout.
+    Warning,
+    Error
+}
+
+/// An error which can be shown in the prompt.
+pub trait PluginError: Error {
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    /// How the user can fix the error.
+    fn hint(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Formats the error together with all of its sources.
+pub fn error_chain(err: &dyn Error) -> String {
+    let mut msg = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        msg.push_str(": ");
+        msg.push_str(&err.to_string());
+        source = err.source();
+    }
+    msg
+}
+
+/// Formats the error together with all of its sources and the hint, if there is one.
+pub fn describe(err: &dyn PluginError) -> String {
+    match err.hint() {
+        Some(hint) => format!("{} ({})", error_chain(err), hint),
+        None => error_chain(err)
+    }
+}
+
+/// Errors of the [`GitPlugin`](crate::GitPlugin).
+#[derive(Debug)]
+pub enum GitError {
+    /// The cwd is not in a repository or the looked up value is not set,
+    /// which is not shown as error.
+    NotAvailable,
+    /// There is no `git` binary.
+    NotInstalled,
+    /// A git command did not finish in time.
+    Timeout { command: &'static str, timeout: Duration },
+    /// A git command could not be run, e.g. because it's not executable.
+    Io { command: &'static str, source: io::Error },
+    /// A git command exited with an error.
+    Failed { command: &'static str, stderr: String },
+    /// The output of a git command could not be parsed.
+    InvalidOutput(String)
+}
+
+impl fmt::Display for GitError {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GitError::NotAvailable => write!(fter, "not available"),
+            GitError::NotInstalled => write!(fter, "git is not installed"),
+            GitError::Timeout { command, timeout } =>
+                write!(fter, "`{}` timed out after {}ms", command, timeout.as_millis()),
+            GitError::Io { command, .. } => write!(fter, "can not run `{}`", command),
+            GitError::Failed { stderr, .. } => write!(fter, "{}", stderr.trim()),
+            GitError::InvalidOutput(msg) => write!(fter, "{}", msg)
+        }
+    }
+}
+
+impl Clone for GitError {
+    fn clone(&self) -> Self {
+        match self {
+            GitError::NotAvailable => GitError::NotAvailable,
+            GitError::NotInstalled => GitError::NotInstalled,
+            GitError::Timeout { command, timeout } => GitError::Timeout { command, timeout: *timeout },
+            GitError::Io { command, source } => GitError::Io { command, source: clone_io_error(source) },
+            GitError::Failed { command, stderr } => GitError::Failed { command, stderr: stderr.clone() },
+            GitError::InvalidOutput(msg) => GitError::InvalidOutput(msg.clone())
+        }
+    }
+}
+
+/// `io::Error` is not `Clone`, so this creates an error with the same kind and message.
+fn clone_io_error(err: &io::Error) -> io::Error {
+    match err.raw_os_error() {
+        Some(code) => io::Error::from_raw_os_error(code),
+        None => io::Error::new(err.kind(), err.to_string())
+    }
+}
+
+impl Error for GitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GitError::Io { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
+
+impl PluginError for GitError {
+    fn severity(&self) -> Severity {
+        match self {
+            GitError::Timeout { .. } => Severity::Warning,
+            _ => Severity::Error
+        }
+    }
+
+    fn hint(&self) -> Option<&str> {
+        match self {
+            GitError::NotInstalled => Some("install git or disable the git segment"),
+            GitError::Timeout { .. } => Some("increase `timeout_ms` in the `[git]` config table"),
+            _ => None
+        }
+    }
+}
+
+/// Errors of the [`CwdPathPlugin`](crate::CwdPathPlugin).
+#[derive(Debug)]
+pub enum CwdError {
+    /// E.g. `$HOME` is not set or the path is not in a repository, which is
+    /// not shown as error.
+    NotAvailable,
+    /// The cwd can not be accessed, e.g. because it was deleted.
+    Cwd(io::Error),
+    /// Given path can not be accessed.
+    Path { path: PathBuf, source: io::Error }
+}
+
+impl fmt::Display for CwdError {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CwdError::NotAvailable => write!(fter, "not available"),
+            CwdError::Cwd(_) => write!(fter, "can not access cwd"),
+            CwdError::Path { path, .. } => write!(fter, "can not access {}", path.display())
+        }
+    }
+}
+
+impl Clone for CwdError {
+    fn clone(&self) -> Self {
+        match self {
+            CwdError::NotAvailable => CwdError::NotAvailable,
+            CwdError::Cwd(source) => CwdError::Cwd(clone_io_error(source)),
+            CwdError::Path { path, source } => CwdError::Path { path: path.clone(), source: clone_io_error(source) }
+        }
+    }
+}
+
+impl Error for CwdError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CwdError::NotAvailable => None,
+            CwdError::Cwd(source) | CwdError::Path { source, .. } => Some(source)
+        }
+    }
+}
+
+impl PluginError for CwdError {}
+
+/// Errors of the [`ColumnCountPlugin`](crate::ColumnCountPlugin).
+#[derive(Debug)]
+pub enum ColumnCountError {
+    Missing,
+    Invalid(OsString)
+}
+
+impl fmt::Display for ColumnCountError {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColumnCountError::Missing => write!(fter, "missing column count argument"),
+            ColumnCountError::Invalid(arg) =>
+                write!(fter, "invalid column count arg: {}", arg.to_string_lossy())
+        }
+    }
+}
+
+impl Error for ColumnCountError {}
+
+impl PluginError for ColumnCountError {
+    fn hint(&self) -> Option<&str> {
+        Some("pass `$COLUMNS` as first argument")
+    }
+}
+
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        write!(fter, "{}", self.msg())
+    }
+}
+
+impl Error for ErrorMessage {}
+
+impl PluginError for ErrorMessage {}
+
+impl From<CwdError> for ErrorMessage {
+    fn from(err: CwdError) -> Self {
+        ErrorMessage::new(error_chain(&err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn errors_are_described_with_sources_and_hints() {
+        let err = GitError::Io {
+            command: "git status",
+            source: io::Error::new(io::ErrorKind::PermissionDenied, "permission denied")
+        };
+        assert_eq!(describe(&err), "can not run `git status`: permission denied");
+
+        let err = GitError::NotInstalled;
+        assert_eq!(describe(&err), "git is not installed (install git or disable the git segment)");
+        assert_eq!(err.severity(), Severity::Error);
+
+        let err = GitError::Timeout { command: "git status", timeout: Duration::from_millis(10) };
+        assert_eq!(err.severity(), Severity::Warning);
+    }
+}
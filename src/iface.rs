@@ -1,10 +1,15 @@
 use std::{
     fmt::Debug,
-    path::PathBuf,
+    ffi::OsString,
+    path::{Path, PathBuf},
     io
 };
 
-#[derive(Debug)]
+use crate::{
+    error::{self, PluginError, GitError, CwdError, ColumnCountError}
+};
+
+#[derive(Debug, Clone)]
 pub struct GitInfo {
     pub branch: String,
     pub has_untracked_files: bool,
@@ -14,42 +19,219 @@ pub struct GitInfo {
 
 /// Function which returns the git info.
 ///
-/// If it fails because there is not git it returns `Err(None)`.
-/// Else it returns `Err(Some(ErrorMessage { .. }))`.
+/// If it fails because the cwd is not in a repository it returns `Err(GitError::NotAvailable)`.
 pub trait GitPlugin {
-    fn lookup_status() -> Result<GitInfo, WithNotAvailableVariant<ErrorMessage>>;
+    fn lookup_status(&self) -> Result<GitInfo, GitError>;
+    /// Returns the url of given remote, `Err(GitError::NotAvailable)` if there is no such remote.
+    fn lookup_remote_url(&self, _remote: &str) -> Result<String, GitError> {
+        Err(GitError::NotAvailable)
+    }
 }
 
 pub trait CwdPathPlugin {
-    fn get_current_path() -> Result<PathBuf, ErrorMessage>;
-    fn get_top_path() -> Result<PathBuf, WithNotAvailableVariant<ErrorMessage>>;
-    fn get_home_path() -> Result<PathBuf, WithNotAvailableVariant<ErrorMessage>>;
+    /// Returns the current working directory.
+    ///
+    /// This should be the logical path (i.e. `$PWD`, not resolving symlinks)
+    /// if it is available.
+    fn get_current_path(&self) -> Result<PathBuf, CwdError>;
+    /// Returns given path with all symlinks resolved.
+    fn get_physical_path(&self, path: &Path) -> Result<PathBuf, CwdError> {
+        Ok(path.to_owned())
+    }
+    /// Returns the last known path of the cwd if it was deleted, `None` otherwise.
+    fn get_deleted_path(&self) -> Option<PathBuf> {
+        None
+    }
+    /// Returns true if the current user can create files in given directory.
+    fn is_writable(&self, _path: &Path) -> bool {
+        true
+    }
+    fn get_top_path(&self) -> Result<PathBuf, CwdError>;
+    fn get_home_path(&self) -> Result<PathBuf, CwdError>;
+    /// Returns the root of the version control repository `path` is in.
+    ///
+    /// Returns `Err(CwdError::NotAvailable)` if it's not in a repository.
+    fn get_vcs_root(&self, _path: &Path) -> Result<PathBuf, CwdError> {
+        Err(CwdError::NotAvailable)
+    }
+    /// Returns the names of all directories in given directory.
+    fn get_sub_dir_names(&self, _path: &Path) -> Result<Vec<OsString>, CwdError> {
+        Err(CwdError::NotAvailable)
+    }
 }
 
 
 /// Function which return the nr. of columns the current terminal has.
 pub trait ColumnCountPlugin {
-    fn get_column_count() -> Result<usize, ErrorMessage>;
+    fn get_column_count(&self) -> Result<usize, ColumnCountError>;
+}
+
+/// The shell a prompt is rendered for.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Shell {
+    #[default]
+    Bash,
+    Zsh
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Which prompt is rendered.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum RenderKind {
+    /// The main (multi-line) prompt.
+    #[default]
+    Prompt,
+    /// A single line prompt shown at the right of the command line, e.g. zsh's `RPROMPT`.
+    Right,
+    /// A minimal form of the prompt, which replaces the prompt once a command was entered.
+    Transient,
+    /// The prompt shown for the following lines of a multi-line command (`PS2`).
+    Continuation,
+    /// The prompt of the `select` shell command (`PS3`).
+    Select
+}
+
+/// What a terminal renders and for which shell.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct RenderTarget {
+    pub shell: Shell,
+    pub kind: RenderKind
+}
+
+/// Where on the prompt line a segment is placed.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Alignment {
+    #[default]
+    Left,
+    /// At the end of the first prompt line.
+    Right
+}
+
+/// How a segment is laid out by the terminal.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SegmentLayout {
+    pub align: Alignment,
+    /// Segments with a lower priority are dropped first if there is not enough space.
+    pub priority: i64
+}
+
+/// Options of a terminal, taken from the `[terminal]` table of the config.
+#[derive(Debug, Clone, Default)]
+pub struct TerminalOptions {
+    /// Report the cwd to the terminal emulator using OSC 7.
+    pub osc7: bool,
+    /// Mark the prompt using OSC 133 (FinalTerm) sequences.
+    pub semantic_prompt: bool,
+    /// Use OSC 8 hyperlinks, if not set they are used if the terminal is known to support them.
+    pub hyperlinks: Option<bool>,
+    /// What to do with the right aligned segments if they do not fit on the first line.
+    pub right_overflow: RightOverflow,
+    /// Max number of lines used for segments, not counting lines of errors.
+    pub max_lines: Option<usize>,
+    /// How the segments are laid out, only used by the box style.
+    pub layout: PromptLayout,
+    /// Use only ASCII separators, if not set they are used if the locale isn't UTF-8.
+    pub ascii: Option<bool>,
+    /// How errors are shown.
+    pub errors: ErrorOptions
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptLayout {
+    /// Segments in a box drawn over multiple lines, the command is entered on the last line.
+    #[default]
+    Box,
+    /// Segments and the prompt ending on a single line if there is enough space,
+    /// else the box layout is used.
+    Compact
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RightOverflow {
+    /// Render them right aligned on a line of their own.
+    #[default]
+    Wrap,
+    /// Do not render them at all.
+    Drop
+}
+
+/// How a terminal shows errors, the `[terminal.errors]` table of the config.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrorOptions {
+    /// Show the errors in full or as a single badge.
+    pub display: ErrorDisplay,
+    /// Show repeated messages only once, together with the number of repeats.
+    pub dedup: bool,
+    /// Scopes of errors which are not shown, e.g. `git`, `cwd` or `columns`.
+    pub suppress: Vec<String>,
+    /// Show errors which likely go away on their own, e.g. timeouts.
+    pub show_warnings: bool
+}
+
+impl Default for ErrorOptions {
+    fn default() -> Self {
+        ErrorOptions {
+            display: Default::default(),
+            dedup: true,
+            suppress: Vec::new(),
+            show_warnings: true
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorDisplay {
+    /// Each error with its full message, on lines of its own.
+    #[default]
+    Full,
+    /// A single `!N` badge with the number of errors, `prompty errors` shows them in full.
+    Compact
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum FormatLike  {
     Lines,
     Text,
     PrimaryText,
     SoftWarning,
     HardWarning,
-    #[allow(unused)]
     ExplicitOk,
     Error,
-    Hidden
+    Hidden,
+    /// The name of the repository the cwd is in.
+    RepoName,
+    /// A user defined name for a directory.
+    PathAlias,
+    /// The physical path of a cwd reached through a symlink.
+    SymlinkTarget,
+    /// Escape sequences for bytes in a path which are not valid UTF-8 or control chars.
+    EscapedBytes
 }
 
 pub trait TerminalPlugin: Sized + Debug {
-    fn new(columns: usize) -> Self;
+    fn new(columns: usize, options: &TerminalOptions, target: &RenderTarget) -> Self;
+    fn column_count(&self) -> usize;
+    /// Called before each segment is rendered, all segments added afterwards use given layout.
+    fn begin_segment(&mut self, layout: &SegmentLayout);
     fn add_text_segment(&mut self, text: &str, fmt_args: FormatLike);
+    /// Adds a single segment made up of multiple differently formatted parts.
+    fn add_text_segment_parts(&mut self, parts: &[(&str, FormatLike)]);
     fn extend_previous_segment(&mut self, text: &str, fmt_args: FormatLike);
     fn add_error_segment(&mut self, scope: &'static str, msg: &str);
+    /// Adds an error segment for an error of a plugin, terminals can use its severity and hint.
+    fn add_error(&mut self, scope: &'static str, err: &dyn PluginError) {
+        self.add_error_segment(scope, &error::describe(err));
+    }
+    /// Makes the last added segment (part) a hyperlink to `url`, if the terminal supports it.
+    fn link_previous_segment(&mut self, _url: &str) {}
+    /// Sets the title of the terminal window.
+    fn set_title(&mut self, _title: &str) {}
+    /// Sets the current working directory, e.g. to report it to the terminal emulator.
+    fn set_cwd(&mut self, _cwd: &Path) {}
     fn flush_to_stdout(&self, prompt_ending: &str);
 }
 
@@ -72,22 +254,3 @@ impl From<io::Error> for ErrorMessage {
         ErrorMessage::new(format!("{}", err))
     }
 }
-
-pub enum WithNotAvailableVariant<T> {
-    Err(T),
-    NotAvailable
-}
-
-
-impl<T> From<T> for WithNotAvailableVariant<T> {
-    fn from(err: T) -> Self {
-        WithNotAvailableVariant::Err(err)
-    }
-}
-
-
-impl From<io::Error> for WithNotAvailableVariant<ErrorMessage> {
-    fn from(err: io::Error) -> Self {
-        ErrorMessage::from(err).into()
-    }
-}
\ No newline at end of file
@@ -0,0 +1,559 @@
+//! Bookmarks and a frecency ranked directory history used by the jump shell function.
+//!
+//! Everything is stored under `$XDG_DATA_HOME/prompty`:
+//!
+//! - `bookmarks`: one `<name>\t<path>` entry per line
+//! - `history`: one `<rank>\t<last access>\t<path>` entry per line
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use crate::{
+    iface::{ErrorMessage, CwdPathPlugin},
+    config::{self, JumpConfig},
+    plugin_impl::CwdPath
+};
+
+const BOOKMARKS_FILE: &str = "bookmarks";
+const HISTORY_FILE: &str = "history";
+/// If the sum of all ranks exceeds this all ranks are aged.
+const MAX_TOTAL_RANK: f64 = 5000.0;
+const AGING_FACTOR: f64 = 0.9;
+/// Entries whose rank falls below this (through aging) are removed.
+const MIN_RANK: f64 = 1.0;
+const LIST_HISTORY_LIMIT: usize = 10;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+const USAGE: &str = "usage: prompty jump (go [<query>...]|add <name> [<dir>]|rm <name>|list|record|complete <word>)";
+
+/// Runs a `prompty jump <sub-command>` command.
+pub fn run_jump_command(args: &[OsString]) -> Result<(), ErrorMessage> {
+    let (cmd, args) = args.split_first()
+        .ok_or_else(|| ErrorMessage::new(USAGE))?;
+
+    let data_dir = config::data_dir()
+        .ok_or_else(|| ErrorMessage::new("can not determine data dir, neither $XDG_DATA_HOME nor $HOME are set"))?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    match (cmd.to_str().unwrap_or(""), args) {
+        ("go", query) => {
+            let bookmarks = Bookmarks::load(&data_dir)?;
+            let history = History::load(&data_dir)?;
+            let cwd = CwdPath.get_current_path()?;
+            let target = resolve(query, &cwd, &bookmarks, &history, now())?;
+            let top = top_path(&target, &bookmarks);
+            out.write_all(target.as_os_str().as_bytes())?;
+            out.write_all(b"\n")?;
+            out.write_all(top.as_os_str().as_bytes())?;
+            out.write_all(b"\n")?;
+        },
+        ("add", [name]) | ("add", [name, _]) => {
+            let name = name.to_str()
+                .filter(|name| is_valid_bookmark_name(name))
+                .ok_or_else(|| ErrorMessage::new("bookmark names can not be empty or contain `/` or whitespace"))?;
+            // like the cwd the dir is kept logical, i.e. symlinks are not resolved
+            let cwd = CwdPath.get_current_path()?;
+            let dir = match args.get(1) {
+                Some(dir) => normalize(&cwd.join(dir)),
+                None => cwd
+            };
+            if !dir.is_dir() {
+                return Err(ErrorMessage::new(format!("{}: not a directory", dir.display())));
+            }
+            let mut bookmarks = Bookmarks::load(&data_dir)?;
+            bookmarks.0.insert(name.to_owned(), dir);
+            bookmarks.save(&data_dir)?;
+        },
+        ("rm", [name]) => {
+            let mut bookmarks = Bookmarks::load(&data_dir)?;
+            let name = name.to_string_lossy();
+            if bookmarks.0.remove(&*name).is_none() {
+                return Err(ErrorMessage::new(format!("no bookmark named {}", name)));
+            }
+            bookmarks.save(&data_dir)?;
+        },
+        ("list", []) => {
+            for (name, path) in Bookmarks::load(&data_dir)?.0 {
+                writeln!(out, "{}\t{}", name, path.display())?;
+            }
+            let history = History::load(&data_dir)?;
+            for (path, score) in history.ranked(now()).into_iter().take(LIST_HISTORY_LIMIT) {
+                writeln!(out, "{:.1}\t{}", score, path.display())?;
+            }
+        },
+        ("record", []) => {
+            let mut history = History::load(&data_dir)?;
+            history.record(&CwdPath.get_current_path()?, now());
+            history.save(&data_dir)?;
+        },
+        ("complete", [word]) => {
+            let bookmarks = Bookmarks::load(&data_dir)?;
+            for candidate in complete(&word.to_string_lossy(), &bookmarks) {
+                writeln!(out, "{}", candidate)?;
+            }
+        },
+        _ => return Err(ErrorMessage::new(USAGE))
+    }
+
+    Ok(())
+}
+
+/// Returns bash code defining the jump function and its completion, and recording
+/// the visited directories in the history.
+///
+/// The history is updated from `PROMPT_COMMAND` whenever `$PWD` changed, so that
+/// directories changed to with `cd` are ranked too.
+///
+/// Like the rest of the setup code it has to be evaluated quoted, i.e.
+/// `eval "$(prompty --bash-setup)"`.
+pub fn bash_function(config: &JumpConfig, exec: &Path) -> Result<String, ErrorMessage> {
+    let name = &config.function_name;
+    if !is_valid_function_name(name) {
+        return Err(ErrorMessage::new(format!("invalid jump function name: {}", name)));
+    }
+
+    Ok(format!(
+        concat!(
+            r#"{name}() {{ local target top; {{ IFS= read -r target && IFS= read -r top; }} < <("{exec}" jump go "$@") || return; "#,
+            r#"cd -- "$target" && export {top_var}="$top"; }}; "#,
+            r#"_prompty_complete_{name}() {{ local IFS=$'\n'; COMPREPLY=($("{exec}" jump complete "$2")); }}; "#,
+            r#"complete -o nospace -o dirnames -F _prompty_complete_{name} {name}; "#,
+            r#"_prompty_record_visit() {{ [[ $PWD == "$__prompty_visited" ]] || {{ __prompty_visited=$PWD; "{exec}" jump record; }}; }}; "#,
+            r#"PROMPT_COMMAND="_prompty_record_visit;$PROMPT_COMMAND";"#
+        ),
+        name=name,
+        exec=exec.display(),
+        top_var=config::PATH_TOP_ENV_VAR
+    ))
+}
+
+fn is_valid_function_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => {},
+        _ => return false
+    }
+    chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-')
+}
+
+fn is_valid_bookmark_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains('/') && !name.chars().any(char::is_whitespace)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Named directories.
+#[derive(Debug, Default)]
+pub struct Bookmarks(pub BTreeMap<String, PathBuf>);
+
+impl Bookmarks {
+
+    pub fn load(data_dir: &Path) -> Result<Self, ErrorMessage> {
+        let mut bookmarks = BTreeMap::new();
+        for line in read_lines(&data_dir.join(BOOKMARKS_FILE))? {
+            let mut fields = line.splitn(2, |bch| *bch == b'\t');
+            if let (Some(name), Some(path)) = (fields.next(), fields.next()) {
+                let name = String::from_utf8_lossy(name).into_owned();
+                bookmarks.insert(name, PathBuf::from(OsStr::from_bytes(path)));
+            }
+        }
+        Ok(Bookmarks(bookmarks))
+    }
+
+    pub fn save(&self, data_dir: &Path) -> Result<(), ErrorMessage> {
+        let mut content = Vec::new();
+        for (name, path) in self.0.iter() {
+            content.extend_from_slice(name.as_bytes());
+            content.push(b'\t');
+            content.extend_from_slice(path.as_os_str().as_bytes());
+            content.push(b'\n');
+        }
+        write_file(&data_dir.join(BOOKMARKS_FILE), &content)
+    }
+
+    /// Returns the bookmark with the longest path containing `path`.
+    fn root_of(&self, path: &Path) -> Option<&Path> {
+        self.0.values()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+            .map(|root| root.as_path())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Visit {
+    path: PathBuf,
+    rank: f64,
+    last_access: u64
+}
+
+impl Visit {
+    fn score(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last_access);
+        let factor =
+            if age < HOUR { 4.0 }
+            else if age < DAY { 2.0 }
+            else if age < WEEK { 0.5 }
+            else { 0.25 };
+        self.rank * factor
+    }
+}
+
+/// The directory history, ranked by "frecency" (frequency and recency).
+#[derive(Debug, Default)]
+pub struct History {
+    visits: Vec<Visit>
+}
+
+impl History {
+
+    pub fn load(data_dir: &Path) -> Result<Self, ErrorMessage> {
+        let mut visits = Vec::new();
+        for line in read_lines(&data_dir.join(HISTORY_FILE))? {
+            let mut fields = line.splitn(3, |bch| *bch == b'\t');
+            let (rank, last_access, path) =
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(rank), Some(last_access), Some(path)) => (rank, last_access, path),
+                    _ => continue
+                };
+            let rank = String::from_utf8_lossy(rank).parse();
+            let last_access = String::from_utf8_lossy(last_access).parse();
+            if let (Ok(rank), Ok(last_access)) = (rank, last_access) {
+                let path = PathBuf::from(OsStr::from_bytes(path));
+                visits.push(Visit { path, rank, last_access });
+            }
+        }
+        Ok(History { visits })
+    }
+
+    pub fn save(&self, data_dir: &Path) -> Result<(), ErrorMessage> {
+        let mut content = Vec::new();
+        for visit in self.visits.iter() {
+            content.extend_from_slice(format!("{}\t{}\t", visit.rank, visit.last_access).as_bytes());
+            content.extend_from_slice(visit.path.as_os_str().as_bytes());
+            content.push(b'\n');
+        }
+        write_file(&data_dir.join(HISTORY_FILE), &content)
+    }
+
+    pub fn record(&mut self, path: &Path, now: u64) {
+        // such paths would break the line based file format
+        if path.as_os_str().as_bytes().contains(&b'\n') {
+            return;
+        }
+        let path = normalize(path);
+
+        match self.visits.iter_mut().find(|visit| visit.path == path) {
+            Some(visit) => {
+                visit.rank += 1.0;
+                visit.last_access = now;
+            },
+            None => self.visits.push(Visit { path, rank: 1.0, last_access: now })
+        }
+
+        let total_rank: f64 = self.visits.iter().map(|visit| visit.rank).sum();
+        if total_rank > MAX_TOTAL_RANK {
+            for visit in self.visits.iter_mut() {
+                visit.rank *= AGING_FACTOR;
+            }
+            self.visits.retain(|visit| visit.rank >= MIN_RANK);
+        }
+    }
+
+    /// Returns all paths with their score, best first.
+    pub fn ranked(&self, now: u64) -> Vec<(&Path, f64)> {
+        let mut ranked = self.visits.iter()
+            .map(|visit| (visit.path.as_path(), visit.score(now)))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(_, left), (_, right)| right.partial_cmp(left).unwrap_or(std::cmp::Ordering::Equal));
+        ranked
+    }
+}
+
+/// Resolves the arguments of the jump function to a directory.
+///
+/// - no arguments resolve to `$HOME`
+/// - a bookmark name (optionally followed by `/sub/path`) resolves to the bookmark
+/// - an existing directory resolves to itself
+/// - else the best ranked directory from the history, which contains all query
+///   words in order with the last one being in the last component, is used
+fn resolve(
+    query: &[OsString],
+    cwd: &Path,
+    bookmarks: &Bookmarks,
+    history: &History,
+    now: u64
+) -> Result<PathBuf, ErrorMessage> {
+    if query.is_empty() {
+        return env::var_os("HOME")
+            .map(PathBuf::from)
+            .ok_or_else(|| ErrorMessage::new("$HOME is not set"));
+    }
+
+    if let [single] = query {
+        let single = Path::new(single);
+        let mut components = single.iter();
+        if let Some(first) = components.next() {
+            if let Some(root) = bookmarks.0.get(&*first.to_string_lossy()) {
+                let target = normalize(&root.join(components.as_path()));
+                if target.is_dir() {
+                    return Ok(target);
+                }
+            }
+        }
+
+        let target = normalize(&cwd.join(single));
+        if target.is_dir() {
+            return Ok(target);
+        }
+    }
+
+    let words = query.iter()
+        .map(|word| word.to_string_lossy().to_lowercase())
+        .collect::<Vec<_>>();
+
+    history.ranked(now).into_iter()
+        .map(|(path, _)| path)
+        .filter(|path| matches_query(path, &words))
+        .find(|path| path.is_dir() && *path != cwd)
+        .map(Path::to_path_buf)
+        .ok_or_else(|| {
+            let query = words.join(" ");
+            ErrorMessage::new(format!("no bookmark, directory or history entry matching: {}", query))
+        })
+}
+
+/// Removes `.` and `..` components without resolving symlinks, like `cd` does
+/// for the logical path.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => { normalized.pop(); },
+                // `/..` is `/`
+                Some(Component::RootDir) => {},
+                _ => normalized.push(component)
+            },
+            other => normalized.push(other)
+        }
+    }
+    normalized
+}
+
+fn matches_query(path: &Path, words: &[String]) -> bool {
+    let full = path.to_string_lossy().to_lowercase();
+    let last_component = path.file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    if let Some(last_word) = words.last() {
+        if !last_component.contains(last_word.as_str()) {
+            return false;
+        }
+    }
+
+    let mut rest = full.as_str();
+    for word in words {
+        match rest.find(word.as_str()) {
+            Some(idx) => rest = &rest[idx + word.len()..],
+            None => return false
+        }
+    }
+    true
+}
+
+/// Returns the path used as `__PS_PATH_TOP` for given target.
+///
+/// For targets in a bookmarked directory the parent of the bookmarked directory is used,
+/// so that the prompt starts with the bookmarked directory. Else the parent of the
+/// (logical) target is used.
+fn top_path(target: &Path, bookmarks: &Bookmarks) -> PathBuf {
+    // the prompt strips the prefix from the logical and the physical cwd,
+    // so bookmarks of both forms work
+    let physical = fs::canonicalize(target).unwrap_or_else(|_| target.to_owned());
+    let anchor = bookmarks.root_of(target)
+        .or_else(|| bookmarks.root_of(&physical))
+        .unwrap_or(target);
+    anchor.parent().unwrap_or(anchor).to_owned()
+}
+
+/// Returns the completions for `word`, i.e. matching bookmarks or sub-directories of them.
+fn complete(word: &str, bookmarks: &Bookmarks) -> Vec<String> {
+    match word.find('/') {
+        None => bookmarks.0.keys()
+            .filter(|name| name.starts_with(word))
+            .map(|name| format!("{}/", name))
+            .collect(),
+        Some(idx) => {
+            let (name, sub_path) = (&word[..idx], &word[idx + 1..]);
+            let root =
+                match bookmarks.0.get(name) {
+                    Some(root) => root,
+                    None => return Vec::new()
+                };
+            let (sub_dir, prefix) =
+                match sub_path.rfind('/') {
+                    Some(idx) => (&sub_path[..=idx], &sub_path[idx + 1..]),
+                    None => ("", sub_path)
+                };
+
+            let entries =
+                match fs::read_dir(root.join(sub_dir)) {
+                    Ok(entries) => entries,
+                    Err(_) => return Vec::new()
+                };
+
+            let mut candidates = entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().map(|ty| ty.is_dir()).unwrap_or(false))
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|dir_name| dir_name.starts_with(prefix) && (!dir_name.starts_with('.') || prefix.starts_with('.')))
+                .map(|dir_name| format!("{}/{}{}/", name, sub_dir, dir_name))
+                .collect::<Vec<_>>();
+            candidates.sort();
+            candidates
+        }
+    }
+}
+
+fn read_lines(path: &Path) -> Result<Vec<Vec<u8>>, ErrorMessage> {
+    match fs::read(path) {
+        Ok(content) => Ok(content
+            .split(|bch| *bch == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_vec())
+            .collect()),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(ErrorMessage::new(format!("can not read {}: {}", path.display(), err)))
+    }
+}
+
+fn write_file(path: &Path, content: &[u8]) -> Result<(), ErrorMessage> {
+    let write = || {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // concurrent shells must not see half written files
+        config::write_atomically(path, content)
+    };
+    write().map_err(|err| ErrorMessage::new(format!("can not write {}: {}", path.display(), err)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bookmarks(entries: &[(&str, &str)]) -> Bookmarks {
+        Bookmarks(entries.iter().map(|(name, path)| (name.to_string(), PathBuf::from(path))).collect())
+    }
+
+    fn history(entries: &[(&str, f64, u64)]) -> History {
+        History {
+            visits: entries.iter()
+                .map(|(path, rank, last_access)| Visit { path: PathBuf::from(path), rank: *rank, last_access: *last_access })
+                .collect()
+        }
+    }
+
+    fn query(words: &[&str]) -> Vec<OsString> {
+        words.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn frecency_prefers_recent_visits() {
+        let now = 10 * WEEK;
+        let history = history(&[
+            ("/old/often", 10.0, now - 2 * WEEK),
+            ("/new/rarely", 2.0, now - 10)
+        ]);
+        let ranked = history.ranked(now).into_iter().map(|(path, _)| path).collect::<Vec<_>>();
+        assert_eq!(ranked, vec![Path::new("/new/rarely"), Path::new("/old/often")]);
+    }
+
+    #[test]
+    fn recording_visits() {
+        let mut history = History::default();
+        history.record(Path::new("/a"), 100);
+        history.record(Path::new("/b"), 110);
+        history.record(Path::new("/a"), 120);
+        assert_eq!(history.visits[0], Visit { path: PathBuf::from("/a"), rank: 2.0, last_access: 120 });
+        assert_eq!(history.visits[1], Visit { path: PathBuf::from("/b"), rank: 1.0, last_access: 110 });
+
+        history.record(Path::new("/a/b/.."), 130);
+        assert_eq!(history.visits.len(), 2);
+        assert_eq!(history.visits[0].rank, 3.0);
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(normalize(Path::new("/a/b/..")), PathBuf::from("/a"));
+        assert_eq!(normalize(Path::new("/a/./b/../../c")), PathBuf::from("/c"));
+        assert_eq!(normalize(Path::new("/..")), PathBuf::from("/"));
+        assert_eq!(normalize(Path::new("../../a")), PathBuf::from("../../a"));
+    }
+
+    #[test]
+    fn query_matching() {
+        let words = |words: &[&str]| words.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+        assert!(matches_query(Path::new("/home/u/src/prompty"), &words(&["prom"])));
+        assert!(matches_query(Path::new("/home/u/src/prompty"), &words(&["src", "prom"])));
+        assert!(!matches_query(Path::new("/home/u/src/prompty"), &words(&["prom", "src"])));
+        assert!(!matches_query(Path::new("/home/u/src/prompty"), &words(&["src"])));
+    }
+
+    #[test]
+    fn resolving_bookmarks_dirs_and_history() {
+        let tmp = env::temp_dir();
+        let tmp_str = tmp.to_str().unwrap();
+        let marks = bookmarks(&[("tmp", tmp_str)]);
+        let history = history(&[(tmp_str, 1.0, 0)]);
+
+        assert_eq!(resolve(&query(&["tmp"]), Path::new("/"), &marks, &history, 0).unwrap(), tmp);
+        assert_eq!(resolve(&query(&[tmp_str]), Path::new("/"), &marks, &History::default(), 0).unwrap(), tmp);
+
+        let last_word = tmp.file_name().unwrap().to_str().unwrap();
+        assert_eq!(resolve(&query(&[last_word]), Path::new("/"), &Bookmarks::default(), &history, 0).unwrap(), tmp);
+        assert!(resolve(&query(&["does-not-exist-anywhere"]), Path::new("/"), &marks, &history, 0).is_err());
+    }
+
+    #[test]
+    fn top_path_uses_bookmark_root() {
+        let marks = bookmarks(&[("acme", "/does/not/exist/acme")]);
+        assert_eq!(top_path(Path::new("/does/not/exist/acme/web/src"), &marks), PathBuf::from("/does/not/exist"));
+        assert_eq!(top_path(Path::new("/does/not/other/dir"), &marks), PathBuf::from("/does/not/other"));
+    }
+
+    #[test]
+    fn completing_bookmark_names() {
+        let marks = bookmarks(&[("acme", "/a"), ("alpha", "/b"), ("beta", "/c")]);
+        assert_eq!(complete("a", &marks), vec!["acme/", "alpha/"]);
+        assert_eq!(complete("x", &marks), Vec::<String>::new());
+    }
+
+    #[test]
+    fn validating_function_names() {
+        assert!(is_valid_function_name("g"));
+        assert!(is_valid_function_name("jump_to-2"));
+        assert!(!is_valid_function_name("2go"));
+        assert!(!is_valid_function_name("g; rm"));
+        assert!(!is_valid_function_name(""));
+    }
+}
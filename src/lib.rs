@@ -0,0 +1,66 @@
+//! Prompty is a (over engineered) bash prompt.
+//!
+//! This library contains everything needed to build a custom prompt
+//! binary, e.g. one with additional segments:
+//!
+//! ```no_run
+//! extern crate prompty;
+//!
+//! use prompty::{PromptBuilder, TerminalPlugin, FormatLike, segment::Segment};
+//! use prompty::plugin_impl::{Terminal, CwdPath, Git, ColumnCount};
+//!
+//! struct Hello;
+//!
+//! impl<T: TerminalPlugin> Segment<T> for Hello {
+//!     fn render(&self, terminal: &mut T) {
+//!         terminal.add_text_segment("hello", FormatLike::Text);
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let columns = ColumnCount::new(std::env::args_os().nth(1));
+//!     PromptBuilder::<Terminal, _, _, _>::new(CwdPath, Git::default(), columns)
+//!         .segment("hello", |_, _| Ok(Box::new(Hello)))
+//!         .run();
+//! }
+//! ```
+extern crate terminfo;
+extern crate smallvec;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+extern crate serde_json;
+extern crate rhai;
+extern crate libc;
+extern crate unicode_width;
+
+pub mod config;
+pub mod error;
+pub mod iface;
+pub mod jump;
+pub mod plugin_impl;
+pub mod segment;
+pub mod shell;
+mod prompt;
+mod process_cwd;
+mod process_git;
+mod process_exit_status;
+mod process_command;
+mod process_script;
+mod process_title;
+mod url;
+#[cfg(test)]
+mod test_utils;
+
+pub use crate::{
+    iface::{
+        TerminalPlugin, GitPlugin, CwdPathPlugin, ColumnCountPlugin,
+        GitInfo, FormatLike, ErrorMessage,
+        Alignment, SegmentLayout, Shell, RenderKind, RenderTarget, TerminalOptions
+    },
+    error::{PluginError, Severity, GitError, CwdError, ColumnCountError},
+    process_cwd::{process_cwd, CwdOptions, Abbreviation},
+    process_git::{process_git, GitOptions},
+    prompt::PromptBuilder
+};
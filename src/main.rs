@@ -1,68 +1,178 @@
-extern crate terminfo;
-extern crate smallvec;
+extern crate prompty;
 
 use std::{
     env,
-    cmp::max
+    ffi::OsString,
+    process
 };
 
-use crate::{
-    iface::*,
-    process_cwd::process_cwd,
-    process_git::process_git
+use prompty::{
+    PromptBuilder,
+    ErrorMessage,
+    RenderTarget,
+    RenderKind,
+    Shell,
+    TerminalPlugin,
+    ColumnCountPlugin,
+    config::{self, Config, TerminalStyle},
+    jump,
+    shell,
+    plugin_impl::{errors, Terminal, PowerlineTerminal, CwdPath, Git, ColumnCount}
 };
 
-mod config;
-mod iface;
-mod plugin_impl;
-mod process_cwd;
-mod process_git;
-
-
 
 fn main() {
     if let Err(()) = try_run_alt_setup_code() {
-        run_with::<
-            plugin_impl::Terminal,
-            plugin_impl::CwdPath,
-            plugin_impl::Git,
-            plugin_impl::ColumnCount
-        >();
+        let args = env::args_os().skip(1).collect::<Vec<_>>();
+        render(RenderTarget::default(), &args);
     }
 }
 
-fn run_with<TERM, PATH, GIT, COL>()
-    where TERM: TerminalPlugin, PATH: CwdPathPlugin, GIT: GitPlugin, COL: ColumnCountPlugin
+/// Renders the prompt, `args` are the column count and the optional exit status.
+fn render(target: RenderTarget, args: &[OsString]) {
+    let (config, config_error) = Config::load_or_default();
+    match config.terminal.style {
+        TerminalStyle::Box => render_with::<Terminal>(config, config_error, target, args),
+        TerminalStyle::Powerline => render_with::<PowerlineTerminal>(config, config_error, target, args)
+    }
+}
+
+fn render_with<TERM>(config: Config, config_error: Option<ErrorMessage>, target: RenderTarget, args: &[OsString])
+    where TERM: TerminalPlugin
 {
-    let (columns, delayed_error) =
-        match COL::get_column_count() {
-            Ok(cols) => (cols, None),
-            Err(err) => (config::FALLBACK_COLUMN_COUNT, Some(err))
-        };
+    let git = Git::new(&config.git);
+    let columns = ColumnCount::new(args.first().cloned());
+
+    let mut prompt = PromptBuilder::<TERM, _, _, _>::new(CwdPath, git, columns)
+        .config(config)
+        .target(target)
+        .exit_status(exit_status_arg(args.get(1)));
+    if let Some(err) = config_error {
+        prompt = prompt.error("config", err);
+    }
+    prompt.run();
+}
 
-    let columns = max(columns, config::MIN_COLUMN_COUNT);
+/// Parses the exit status of the last command.
+fn exit_status_arg(arg: Option<&OsString>) -> Option<i32> {
+    arg.and_then(|arg| arg.to_str().and_then(|arg| arg.parse().ok()))
+}
+
+/// The arguments of `prompty render`.
+#[derive(Default)]
+struct RenderArgs<'a> {
+    target: RenderTarget,
+    /// The previous prompt, if given together with the history entry of the command
+    /// the transient prompt replaces it (for shells which can't do so themselves).
+    previous_prompt: Option<String>,
+    history_entry: Option<String>,
+    /// The history entry before the command was entered.
+    previous_history_entry: Option<String>,
+    continuation_prompt: Option<String>,
+    /// The column count and exit status.
+    rest: &'a [OsString]
+}
 
-    let mut terminal = TERM::new(columns);
-    if let Some(err) = delayed_error {
-        terminal.add_error_segment("columns", err.msg());
+/// Parses the options of `prompty render`.
+fn parse_render_args(mut args: &[OsString]) -> Result<RenderArgs<'_>, String> {
+    let mut render_args = RenderArgs::default();
+    while let Some(option) = args.first().and_then(|arg| arg.to_str()) {
+        if !option.starts_with("--") {
+            break;
+        }
+        let value = args.get(1)
+            .map(|arg| arg.to_string_lossy())
+            .ok_or_else(|| format!("missing value for {}", option))?;
+        match (option, &*value) {
+            ("--shell", "bash") => render_args.target.shell = Shell::Bash,
+            ("--shell", "zsh") => render_args.target.shell = Shell::Zsh,
+            ("--kind", "prompt") => render_args.target.kind = RenderKind::Prompt,
+            ("--kind", "right") => render_args.target.kind = RenderKind::Right,
+            ("--kind", "transient") => render_args.target.kind = RenderKind::Transient,
+            ("--kind", "continuation") => render_args.target.kind = RenderKind::Continuation,
+            ("--kind", "select") => render_args.target.kind = RenderKind::Select,
+            ("--shell", _) | ("--kind", _) => return Err(format!("invalid value for {}: {}", option, value)),
+            ("--previous-prompt", _) => render_args.previous_prompt = Some(value.into_owned()),
+            ("--history-entry", _) => render_args.history_entry = Some(value.into_owned()),
+            ("--previous-history-entry", _) => render_args.previous_history_entry = Some(value.into_owned()),
+            ("--continuation-prompt", _) => render_args.continuation_prompt = Some(value.into_owned()),
+            _ => return Err(format!("unknown option: {}", option))
+        }
+        args = &args[2..];
     }
-    process_cwd::<PATH, _>(&mut terminal);
-    process_git::<GIT, _>(&mut terminal);
-    terminal.flush_to_stdout(config::PROMPT_ENDING);
+    render_args.rest = args;
+    Ok(render_args)
 }
 
 fn try_run_alt_setup_code() -> Result<(), ()> {
-    let first_relevant_arg = env::args_os().skip(1).next();
+    let first_relevant_arg = env::args_os().nth(1);
 
     if let Some(arg) = first_relevant_arg {
         if let Some(arg) = arg.to_str() {
+            if arg == "jump" {
+                let args = env::args_os().skip(2).collect::<Vec<_>>();
+                if let Err(err) = jump::run_jump_command(&args) {
+                    eprintln!("prompty jump: {}", err.msg());
+                    process::exit(1);
+                }
+                return Ok(());
+            }
+
+            if arg == "errors" {
+                if let Err(err) = errors::print_last_errors() {
+                    eprintln!("prompty errors: {}", err.msg());
+                    process::exit(1);
+                }
+                return Ok(());
+            }
+
+            if arg == "render" {
+                let args = env::args_os().skip(2).collect::<Vec<_>>();
+                match parse_render_args(&args) {
+                    Ok(RenderArgs {
+                        target,
+                        previous_prompt: Some(prompt),
+                        history_entry: Some(entry),
+                        previous_history_entry,
+                        continuation_prompt,
+                        rest
+                    }) => {
+                        // if the command wasn't added to the history it's unknown, so the prompt is kept
+                        let is_new_entry = previous_history_entry
+                            .is_none_or(|previous| shell::is_new_history_entry(&previous, &entry));
+                        if is_new_entry {
+                            let columns = ColumnCount::new(rest.first().cloned()).get_column_count()
+                                .unwrap_or(config::FALLBACK_COLUMN_COUNT);
+                            let command = shell::command_from_history(&entry);
+                            let continuation = continuation_prompt.as_deref().unwrap_or("");
+                            print!("{}", shell::transient_redraw(&prompt, continuation, command, columns));
+                            render(target, rest);
+                            println!("{}", command);
+                        }
+                    },
+                    Ok(RenderArgs { target, rest, .. }) => render(target, rest),
+                    Err(msg) => {
+                        eprintln!("prompty render: {}", msg);
+                        process::exit(1);
+                    }
+                }
+                return Ok(());
+            }
+
             if arg.starts_with("-") {
-                if arg == "--bash-setup" {
+                if arg == "--bash-setup" || arg == "--zsh-setup" {
                     let exec = env::current_exe().unwrap();
-                    println!(r#"
-                        g() {{ cd "$1"; export __PS_PATH_TOP="$(dirname $(pwd -P))"; }};
-                        PS1='$("{exec}" $COLUMNS)'
-                    "#, exec=exec.display());
+                    let (config, config_error) = Config::load_or_default();
+                    let (code, setup_error) =
+                        if arg == "--bash-setup" {
+                            shell::bash_setup(&config, &exec)
+                        } else {
+                            shell::zsh_setup(&config, &exec)
+                        };
+                    for err in config_error.iter().chain(setup_error.iter()) {
+                        eprintln!("prompty: {}", err.msg());
+                    }
+                    println!("{}", code);
                 } else {
                     println!("{}", HELP_MSG);
                 }
@@ -76,16 +186,59 @@ fn try_run_alt_setup_code() -> Result<(), ()> {
 }
 
 const HELP_MSG: &str =
-r#"usage: prompty (--bash-setup|<column_count>)
+r#"usage: prompty (--bash-setup|--zsh-setup|errors|jump <command>...|<column_count> [<exit_status>])
+       prompty render [--shell bash|zsh] [--kind <kind>] <column_count> [<exit_status>]
 
 If `--bash-setup` is passed in a but of bash code
 will be emmited which if passed to a `eval` call
 will setup `prompty` as promt and add the `g`
-command.
+jump function (the name can be changed with
+`function_name` in the `[jump]` config table).
+Quote it: `eval "$(prompty --bash-setup)"`.
+
+`--zsh-setup` does the same for zsh, setting up
+`PROMPT` and `RPROMPT` (without the jump function).
+
+`prompty jump` manages the bookmarks and directory
+history used by the jump function (`record` adds
+the cwd to the history, which the bash setup does
+whenever the cwd changed):
+
+    prompty jump add <name> [<dir>]
+    prompty jump rm <name>
+    prompty jump list
+    prompty jump record
+    prompty jump go [<bookmark>[/<sub/dir>]|<dir>|<query>...]
 
 Note that `--bash-setup` only works well with paths
 to exec which do not need any special escape sequences.
 White spaces are handled.
 
+`prompty errors` prints the errors of the last
+prompt in full, including suppressed ones.
+
 Else if a column count is passed in, it will emit
-the `prompty` promt."#;
+the `prompty` promt. The optional exit status of the
+last command is used by the `exit_status` segment.
+`prompty render` does the same, but allows choosing
+the shell to escape the prompt for and whether the
+main prompt (`prompt`), the right prompt (`right`),
+the transient prompt which replaces the prompt of
+entered commands (`transient`), the prompt of
+multi-line commands (`continuation`, `PS2`) or the
+prompt of `select` (`select`, `PS3`) is rendered.
+
+Which segments are shown in which order can be
+configured in `$XDG_CONFIG_HOME/prompty/config.toml`
+(or the file `$PROMPTY_CONFIG` points to), e.g.:
+
+    segments = ["cwd", "git", "exit_status", "env"]
+    rprompt_segments = ["env"]
+
+    [segment.exit_status]
+    show_success = true
+
+    [segment.env]
+    type = "command"
+    command = "cat .deploy-env"
+    when = "test -f .deploy-env""#;
@@ -0,0 +1,237 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    env,
+    error::Error,
+    fmt,
+    fs,
+    hash::{Hash, Hasher},
+    io::{self, Read},
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::{Child, Command, Stdio, ExitStatus},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant}
+};
+
+use libc;
+
+use crate::{
+    iface::ErrorMessage,
+    error::{error_chain, PluginError, Severity},
+    config
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+#[derive(Debug)]
+pub struct CommandOutput {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    /// The command could not be started, e.g. because it does not exist.
+    Spawn { command: String, source: io::Error },
+    Timeout { command: String, timeout: Duration },
+    /// Waiting for the command or reading its output failed.
+    Io(io::Error)
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::Spawn { command, .. } => write!(fter, "can not run `{}`", command),
+            CommandError::Timeout { command, timeout } =>
+                write!(fter, "`{}` timed out after {}ms", command, timeout.as_millis()),
+            CommandError::Io(_) => write!(fter, "can not read command output")
+        }
+    }
+}
+
+impl Error for CommandError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CommandError::Spawn { source, .. } | CommandError::Io(source) => Some(source),
+            CommandError::Timeout { .. } => None
+        }
+    }
+}
+
+impl PluginError for CommandError {
+    fn severity(&self) -> Severity {
+        match self {
+            CommandError::Timeout { .. } => Severity::Warning,
+            _ => Severity::Error
+        }
+    }
+
+    fn hint(&self) -> Option<&str> {
+        match self {
+            CommandError::Spawn { source, .. } if source.kind() == io::ErrorKind::NotFound =>
+                Some("make sure the command is installed and in `$PATH`"),
+            CommandError::Timeout { .. } => Some("increase `timeout_ms` of the segment"),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for CommandError {
+    fn from(err: io::Error) -> Self {
+        CommandError::Io(err)
+    }
+}
+
+impl From<CommandError> for ErrorMessage {
+    fn from(err: CommandError) -> Self {
+        ErrorMessage::new(error_chain(&err))
+    }
+}
+
+/// Runs given command using `sh -c`, killing it if it takes longer then `timeout`.
+pub fn run_shell_command(command: &str, timeout: Duration) -> Result<CommandOutput, CommandError> {
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]);
+    run_with_timeout(shell, command, timeout)
+}
+
+/// Runs given command, killing it if it takes longer then `timeout`.
+///
+/// The timeout also covers reading the output, which can stay open after the
+/// command exited, e.g. if it started a background job. The command is run in
+/// a process group of its own, so that such jobs are killed too.
+///
+/// The `display_name` is used in error messages.
+pub fn run_with_timeout(mut command: Command, display_name: &str, timeout: Duration)
+    -> Result<CommandOutput, CommandError>
+{
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(|source| CommandError::Spawn { command: display_name.to_owned(), source })?;
+    let timed_out = || CommandError::Timeout { command: display_name.to_owned(), timeout };
+
+    // read concurrently, as commands with a lot of output would block on a full pipe
+    let stdout = child.stdout.take().map(read_in_background);
+    let stderr = child.stderr.take().map(read_in_background);
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            kill_process_group(&child);
+            let _ = child.wait();
+            return Err(timed_out());
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    match (join_reader(stdout, deadline), join_reader(stderr, deadline)) {
+        (Some(stdout), Some(stderr)) => Ok(CommandOutput { status, stdout: stdout?, stderr: stderr? }),
+        _ => {
+            kill_process_group(&child);
+            Err(timed_out())
+        }
+    }
+}
+
+/// Kills the process group the child leads, including e.g. background jobs started by it.
+fn kill_process_group(child: &Child) {
+    let group = child.id() as libc::pid_t;
+    // SAFETY: `kill` doesn't access memory of this process, and the group id can't be
+    // reused as long as a process of the group exists. Errors (e.g. if all processes
+    // exited in between) are ignored, as there is nothing left to kill then.
+    unsafe {
+        libc::kill(-group, libc::SIGKILL);
+    }
+}
+
+fn read_in_background(mut pipe: impl Read + Send + 'static) -> Receiver<io::Result<Vec<u8>>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let res = pipe.read_to_end(&mut buf).map(|_| buf);
+        let _ = sender.send(res);
+    });
+    receiver
+}
+
+/// Waits for the output read by [`read_in_background`], `None` if it's not complete at the deadline.
+fn join_reader(reader: Option<Receiver<io::Result<Vec<u8>>>>, deadline: Instant) -> Option<io::Result<Vec<u8>>> {
+    let reader =
+        match reader {
+            Some(reader) => reader,
+            None => return Some(Ok(Vec::new()))
+        };
+    match reader.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(res) => Some(res),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => Some(Err(io::Error::other("reading command output failed")))
+    }
+}
+
+/// Cache for the stdout of commands which succeeded.
+///
+/// Entries are keyed by the segment name, the command and the current
+/// working directory, as most commands depend on it.
+pub struct CommandCache {
+    file_name: String,
+    ttl: Duration
+}
+
+impl CommandCache {
+
+    pub fn new(name: &str, command: &str, ttl: Duration) -> Self {
+        let mut hasher = DefaultHasher::new();
+        command.hash(&mut hasher);
+        env::current_dir().ok().hash(&mut hasher);
+        let file_name = format!("{}-{:016x}", name, hasher.finish());
+
+        CommandCache { file_name, ttl }
+    }
+
+    fn path(&self) -> io::Result<PathBuf> {
+        Ok(config::runtime_sub_dir("command-cache")?.join(&self.file_name))
+    }
+
+    /// Returns the cached output if there is one which is not older then the ttl.
+    pub fn get(&self) -> Option<String> {
+        let path = self.path().ok()?;
+        let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok()?;
+        let age = modified.elapsed().ok()?;
+        if age > self.ttl {
+            return None;
+        }
+        fs::read_to_string(&path).ok()
+    }
+
+    pub fn put(&self, output: &str) -> Result<(), ErrorMessage> {
+        config::write_atomically(&self.path()?, output.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn background_jobs_keeping_the_output_open_time_out() {
+        let start = Instant::now();
+        let res = run_shell_command("sleep 5 & echo hi", Duration::from_millis(200));
+        assert!(matches!(res, Err(CommandError::Timeout { .. })));
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let output = run_shell_command("echo hi", Duration::from_millis(1000)).unwrap();
+        assert_eq!(output.stdout, b"hi\n");
+    }
+}
@@ -1,37 +1,122 @@
-use crate::iface::{CwdPathPlugin, ErrorMessage, WithNotAvailableVariant};
+use crate::{
+    iface::CwdPathPlugin,
+    error::CwdError
+};
 
 use std::{
-    path::PathBuf,
-    ffi::OsStr,
-    env
+    path::{Path, PathBuf, Component},
+    ffi::{OsStr, OsString, CString},
+    os::unix::{fs::MetadataExt, ffi::OsStrExt},
+    env,
+    fs,
+    io
 };
 
+use libc;
+
 use crate::config;
 
+/// Files/directories marking the root of a version control repository.
+const VCS_ROOT_MARKERS: &[&str] = &[".git", ".hg", ".svn", ".bzr", "_darcs", ".pijul", ".fslckout"];
+
+#[derive(Debug, Default)]
 pub struct CwdPath;
 
 impl CwdPathPlugin for CwdPath {
-    fn get_current_path() -> Result<PathBuf, ErrorMessage> {
-        env::current_dir()
-            .map_err(|e| ErrorMessage::new(format!("can not access cwd: {}", e)))
+    fn get_current_path(&self) -> Result<PathBuf, CwdError> {
+        let physical = env::current_dir().map_err(CwdError::Cwd)?;
+        Ok(get_logical_path(&physical).unwrap_or(physical))
+    }
+
+    fn get_physical_path(&self, path: &Path) -> Result<PathBuf, CwdError> {
+        fs::canonicalize(path)
+            .map_err(|source| CwdError::Path { path: path.to_owned(), source })
     }
 
-    fn get_top_path() -> Result<PathBuf, WithNotAvailableVariant<ErrorMessage>> {
+    fn get_deleted_path(&self) -> Option<PathBuf> {
+        match env::current_dir() {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                env::var_os("PWD")
+                    .map(PathBuf::from)
+                    .filter(|pwd| pwd.is_absolute())
+            },
+            _ => None
+        }
+    }
+
+    fn is_writable(&self, path: &Path) -> bool {
+        match CString::new(path.as_os_str().as_bytes()) {
+            Ok(c_path) => can_write(&c_path),
+            // such paths can't exist, so there is nothing to warn about
+            Err(_) => true
+        }
+    }
+
+    fn get_top_path(&self) -> Result<PathBuf, CwdError> {
         get_env_path(config::PATH_TOP_ENV_VAR)
     }
 
-    fn get_home_path() -> Result<PathBuf, WithNotAvailableVariant<ErrorMessage>> {
+    fn get_home_path(&self) -> Result<PathBuf, CwdError> {
         get_env_path("HOME")
     }
+
+    fn get_vcs_root(&self, path: &Path) -> Result<PathBuf, CwdError> {
+        path.ancestors()
+            .find(|dir| VCS_ROOT_MARKERS.iter().any(|marker| dir.join(marker).exists()))
+            .map(Path::to_path_buf)
+            .ok_or(CwdError::NotAvailable)
+    }
+
+    fn get_sub_dir_names(&self, path: &Path) -> Result<Vec<OsString>, CwdError> {
+        let read_names = || -> io::Result<Vec<OsString>> {
+            let mut names = Vec::new();
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    names.push(entry.file_name());
+                }
+            }
+            Ok(names)
+        };
+        read_names().map_err(|source| CwdError::Path { path: path.to_owned(), source })
+    }
+}
+
+/// Returns `$PWD` if it's a valid (absolute and normalized) path to the `physical` cwd.
+///
+/// The shell keeps `$PWD` up to date, it differs from the physical path if
+/// the cwd was entered through a symlink.
+fn get_logical_path(physical: &Path) -> Option<PathBuf> {
+    let pwd = PathBuf::from(env::var_os("PWD")?);
+    let is_normalized = pwd.is_absolute() && pwd.components()
+        .all(|component| component != Component::CurDir && component != Component::ParentDir);
+    if !is_normalized {
+        return None;
+    }
+
+    let (pwd_meta, physical_meta) = (fs::metadata(&pwd).ok()?, fs::metadata(physical).ok()?);
+    if pwd_meta.dev() == physical_meta.dev() && pwd_meta.ino() == physical_meta.ino() {
+        Some(pwd)
+    } else {
+        None
+    }
 }
 
+/// Returns true if the real user can write to `path`, as checked by `access(2)`.
+///
+/// Unlike checking the permission bits this also takes ACLs, read-only mounts
+/// and the groups of the user into account, like the shell's `test -w` does.
+fn can_write(path: &CString) -> bool {
+    // SAFETY: `access` only reads the null terminated string, which outlives the call
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
 
-fn get_env_path(env_var: impl AsRef<OsStr>) -> Result<PathBuf, WithNotAvailableVariant<ErrorMessage>> {
+fn get_env_path(env_var: impl AsRef<OsStr>) -> Result<PathBuf, CwdError> {
     env::var_os(env_var)
-        .ok_or_else(|| WithNotAvailableVariant::NotAvailable)
+        .ok_or(CwdError::NotAvailable)
         .and_then(|osstr| {
             if osstr.is_empty() {
-                Err(WithNotAvailableVariant::NotAvailable)
+                Err(CwdError::NotAvailable)
             } else {
                 Ok(PathBuf::from(osstr))
             }
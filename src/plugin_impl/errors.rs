@@ -0,0 +1,299 @@
+//! Error segments shared by the terminal plugins, and the log of the errors
+//! of the last prompt shown by `prompty errors`.
+use std::{
+    borrow::Cow,
+    fs,
+    io::{self, Write},
+    path::Path
+};
+
+use libc;
+
+use crate::{
+    config,
+    error::{self, PluginError, Severity},
+    iface::{ErrorMessage, FormatLike, ErrorOptions, ErrorDisplay}
+};
+
+/// An error as it is shown in the prompt.
+#[derive(Debug)]
+pub(super) struct ErrorSegment {
+    pub(super) scope: &'static str,
+    pub(super) msg: String,
+    pub(super) hint: Option<String>,
+    pub(super) severity: Severity,
+    /// How often the message was added, always 1 if dedup is disabled.
+    pub(super) count: usize
+}
+
+impl ErrorSegment {
+
+    /// The message, with the number of repeats if there are any and the hint.
+    pub(super) fn text(&self) -> Cow<'_, str> {
+        let msg = self.msg.trim();
+        match (self.count, &self.hint) {
+            (1, None) => Cow::Borrowed(msg),
+            (1, Some(hint)) => Cow::Owned(format!("{} ({})", msg, hint)),
+            (count, None) => Cow::Owned(format!("{} (×{})", msg, count)),
+            (count, Some(hint)) => Cow::Owned(format!("{} (×{}) ({})", msg, count, hint))
+        }
+    }
+
+    pub(super) fn format(&self) -> FormatLike {
+        match self.severity {
+            Severity::Warning => FormatLike::SoftWarning,
+            Severity::Error => FormatLike::Error
+        }
+    }
+}
+
+/// Collects the errors added to a terminal, applying the `[terminal.errors]` options.
+#[derive(Debug)]
+pub(super) struct ErrorSegments {
+    config: ErrorOptions,
+    /// All errors, including suppressed ones.
+    all: Vec<(&'static str, String)>,
+    shown: Vec<ErrorSegment>
+}
+
+impl ErrorSegments {
+
+    pub(super) fn new(config: &ErrorOptions) -> Self {
+        ErrorSegments { config: config.clone(), all: Vec::new(), shown: Vec::new() }
+    }
+
+    pub(super) fn add(&mut self, scope: &'static str, msg: &str) {
+        self.push(ErrorSegment { scope, msg: msg.to_owned(), hint: None, severity: Severity::Error, count: 1 });
+    }
+
+    /// Adds an error of a plugin, using its severity and hint.
+    pub(super) fn add_error(&mut self, scope: &'static str, err: &dyn PluginError) {
+        let msg = error::error_chain(err);
+        let hint = err.hint().map(str::to_owned);
+        self.push(ErrorSegment { scope, msg, hint, severity: err.severity(), count: 1 });
+    }
+
+    fn push(&mut self, error: ErrorSegment) {
+        self.all.push((error.scope, error.text().into_owned()));
+        let suppressed = self.config.suppress.iter().any(|scope| scope == error.scope)
+            || (error.severity == Severity::Warning && !self.config.show_warnings);
+        if suppressed {
+            return;
+        }
+        if self.config.dedup {
+            let existing = self.shown.iter_mut()
+                .find(|shown| shown.scope == error.scope && shown.msg == error.msg);
+            if let Some(existing) = existing {
+                existing.count += 1;
+                return;
+            }
+        }
+        self.shown.push(error);
+    }
+
+    /// Errors to render in full, none if they are shown as badge.
+    pub(super) fn full(&self) -> &[ErrorSegment] {
+        match self.config.display {
+            ErrorDisplay::Full => &self.shown,
+            ErrorDisplay::Compact => &[]
+        }
+    }
+
+    /// The `!N` badge shown instead of the errors, if they are shown as badge.
+    pub(super) fn badge(&self) -> Option<String> {
+        let count = self.shown.iter().map(|err| err.count).sum::<usize>();
+        match self.config.display {
+            ErrorDisplay::Compact if count > 0 => Some(format!("!{}", count)),
+            _ => None
+        }
+    }
+
+    /// Saves all errors, including suppressed ones, for `prompty errors`.
+    ///
+    /// Failing to do so is ignored, as there is no way left to report it.
+    pub(super) fn save(&self) {
+        let shell = Shell::parent();
+        let dir = match config::runtime_sub_dir("errors") {
+            Ok(dir) => dir,
+            Err(_) => return
+        };
+        let path = dir.join(shell.pid.to_string());
+        if self.all.is_empty() {
+            let _ = fs::remove_file(&path);
+            return;
+        }
+        // without `/proc` there is no telling whether a shell still runs
+        if !shell.start_time.is_empty() {
+            remove_stale_errors(&dir);
+        }
+        let content = format!("{}\n{}", shell.start_time, format_errors(&self.all));
+        let _ = config::write_atomically(&path, content.as_bytes());
+    }
+}
+
+fn format_errors(errors: &[(&'static str, String)]) -> String {
+    errors.iter()
+        .map(|(scope, msg)| format!("{}: {}\n", scope, msg.trim()))
+        .collect()
+}
+
+/// The shell whose prompt is rendered, i.e. the parent process.
+///
+/// The errors are saved per shell pid, together with the start time of the shell
+/// so that errors of an exited shell aren't shown by a new one with the same pid.
+struct Shell {
+    pid: libc::pid_t,
+    start_time: String
+}
+
+impl Shell {
+    fn parent() -> Self {
+        let pid = unsafe { libc::getppid() };
+        Shell { pid, start_time: start_time(pid).unwrap_or_default() }
+    }
+}
+
+/// The start time of the process in clock ticks since boot, as given in `/proc/<pid>/stat`.
+fn start_time(pid: libc::pid_t) -> Option<String> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the fields following the command name, which may contain spaces and parens
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19).map(str::to_owned)
+}
+
+/// Splits saved errors into the start time of the shell and the errors.
+fn parse_saved(content: &str) -> (&str, &str) {
+    content.split_once('\n').unwrap_or((content, ""))
+}
+
+/// Removes the errors saved for shells which exited since.
+fn remove_stale_errors(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let pid = match entry.file_name().to_str().and_then(|name| name.parse().ok()) {
+            Some(pid) => pid,
+            None => continue
+        };
+        let stale = match (fs::read_to_string(entry.path()), start_time(pid)) {
+            (Ok(content), Some(start_time)) => parse_saved(&content).0 != start_time,
+            (Ok(_), None) => true,
+            (Err(_), _) => false
+        };
+        if stale {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Prints the errors of the last prompt rendered for the calling shell, nothing if there were none.
+pub fn print_last_errors() -> Result<(), ErrorMessage> {
+    let shell = Shell::parent();
+    let path = config::runtime_sub_dir("errors")?.join(shell.pid.to_string());
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            let (start_time, errors) = parse_saved(&content);
+            if start_time == shell.start_time {
+                io::stdout().write_all(errors.as_bytes())?;
+            }
+        },
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+        Err(err) => return Err(err.into())
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use crate::error::GitError;
+
+    fn errors(display: ErrorDisplay, dedup: bool, suppress: &[&str]) -> ErrorSegments {
+        let config = ErrorOptions {
+            display,
+            dedup,
+            suppress: suppress.iter().map(|scope| scope.to_string()).collect(),
+            ..Default::default()
+        };
+        let mut errors = ErrorSegments::new(&config);
+        errors.add("git", "git not found");
+        errors.add("cwd", "permission denied");
+        errors.add("git", "git not found");
+        errors
+    }
+
+    fn texts(errors: &ErrorSegments) -> Vec<String> {
+        errors.full().iter().map(|err| format!("{}: {}", err.scope, err.text())).collect()
+    }
+
+    #[test]
+    fn repeated_errors_are_deduplicated() {
+        let errors = errors(ErrorDisplay::Full, true, &[]);
+        assert_eq!(texts(&errors), ["git: git not found (×2)", "cwd: permission denied"]);
+        assert_eq!(errors.badge(), None);
+
+        let errors = self::errors(ErrorDisplay::Full, false, &[]);
+        assert_eq!(texts(&errors), ["git: git not found", "cwd: permission denied", "git: git not found"]);
+    }
+
+    #[test]
+    fn suppressed_scopes_are_not_shown_but_saved() {
+        let errors = errors(ErrorDisplay::Full, true, &["git"]);
+        assert_eq!(texts(&errors), ["cwd: permission denied"]);
+        assert_eq!(
+            format_errors(&errors.all),
+            "git: git not found\ncwd: permission denied\ngit: git not found\n"
+        );
+    }
+
+    #[test]
+    fn plugin_errors_have_a_severity_and_hint() {
+        let mut errors = ErrorSegments::new(&ErrorOptions::default());
+        errors.add_error("git", &GitError::NotInstalled);
+        errors.add_error("git", &GitError::Timeout { command: "git status", timeout: Duration::from_millis(5) });
+        assert_eq!(texts(&errors), [
+            "git: git is not installed (install git or disable the git segment)",
+            "git: `git status` timed out after 5ms (increase `timeout_ms` in the `[git]` config table)"
+        ]);
+        let formats = errors.full().iter().map(ErrorSegment::format).collect::<Vec<_>>();
+        assert_eq!(formats, [FormatLike::Error, FormatLike::SoftWarning]);
+
+        let config = ErrorOptions { show_warnings: false, ..Default::default() };
+        let mut errors = ErrorSegments::new(&config);
+        errors.add_error("git", &GitError::Timeout { command: "git status", timeout: Duration::from_millis(5) });
+        assert!(errors.full().is_empty());
+        assert_eq!(errors.all.len(), 1);
+    }
+
+    #[test]
+    fn errors_of_exited_shells_are_removed() {
+        let pid = std::process::id();
+        let current = start_time(pid as libc::pid_t).unwrap();
+        let dir = std::env::temp_dir().join(format!("prompty-errors-test-{}", pid));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(pid.to_string()), format!("{}\ngit: failed\n", current)).unwrap();
+        // a running process, but not the one which saved the errors
+        let other = unsafe { libc::getppid() }.to_string();
+        fs::write(dir.join(&other), "0\ngit: failed\n").unwrap();
+
+        remove_stale_errors(&dir);
+        assert!(dir.join(pid.to_string()).exists());
+        assert!(!dir.join(&other).exists());
+        assert_eq!(parse_saved(&format!("{}\ngit: failed\n", current)), (&*current, "git: failed\n"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compact_display_shows_a_badge() {
+        let errors = errors(ErrorDisplay::Compact, true, &[]);
+        assert!(texts(&errors).is_empty());
+        assert_eq!(errors.badge(), Some("!3".to_owned()));
+
+        let errors = self::errors(ErrorDisplay::Compact, true, &["git", "cwd"]);
+        assert_eq!(errors.badge(), None);
+    }
+}
@@ -1,45 +1,88 @@
 use std::{
     process::Command,
-    io::BufRead
+    io::{self, BufRead},
+    time::Duration
 };
 
-use crate::iface::{GitInfo, GitPlugin, ErrorMessage, WithNotAvailableVariant};
+use crate::{
+    iface::{GitInfo, GitPlugin},
+    error::GitError,
+    config::GitConfig
+};
+
+use super::command::{run_with_timeout, CommandOutput, CommandError};
 
 const UNMODIFIED_SHORT_STATUS_CODE: u8 = b' ';
 
-pub struct Git;
+#[derive(Debug)]
+pub struct Git {
+    timeout: Duration
+}
+
+impl Git {
+    pub fn new(config: &GitConfig) -> Self {
+        Git { timeout: Duration::from_millis(config.timeout_ms) }
+    }
+
+    /// Runs git with given args, `command` is used in error messages.
+    fn run(&self, args: &[&str], command: &'static str) -> Result<CommandOutput, GitError> {
+        let mut git = Command::new("git");
+        git.args(args);
+        run_with_timeout(git, command, self.timeout)
+            .map_err(|err| match err {
+                CommandError::Spawn { ref source, .. } if source.kind() == io::ErrorKind::NotFound => {
+                    GitError::NotInstalled
+                },
+                CommandError::Spawn { source, .. } | CommandError::Io(source) => GitError::Io { command, source },
+                CommandError::Timeout { timeout, .. } => GitError::Timeout { command, timeout }
+            })
+    }
+}
+
+impl Default for Git {
+    fn default() -> Self {
+        Git::new(&GitConfig::default())
+    }
+}
 
 impl GitPlugin for Git {
-    fn lookup_status() -> Result<GitInfo, WithNotAvailableVariant<ErrorMessage>> {
-        let output_res = Command::new("git")
-            .args(&["status", "-s", "-b"])
-            .output();
-
-        let output =
-            match output_res {
-                Ok(output) => output,
-                Err(err) => {
-                    let err = ErrorMessage::new(format!("{}", err));
-                    return Err(WithNotAvailableVariant::Err(err));
-                }
-            };
+    fn lookup_status(&self) -> Result<GitInfo, GitError> {
+        let output = self.run(&["status", "-s", "-b"], "git status")?;
 
         if let Some(128) = output.status.code() {
-            if output.stderr.starts_with(b"fatal: not a git repository") {
-                return Err(WithNotAvailableVariant::NotAvailable);
+            // the cwd segment already reports a deleted cwd
+            if output.stderr.starts_with(b"fatal: not a git repository")
+                || output.stderr.starts_with(b"fatal: Unable to read current working directory")
+            {
+                return Err(GitError::NotAvailable);
             }
         }
 
         if output.status.success() {
             parse_git_info(&output.stdout)
         } else {
-            let err = ErrorMessage::new(String::from_utf8_lossy(&output.stderr));
-            Err(WithNotAvailableVariant::Err(err))
+            let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+            Err(GitError::Failed { command: "git status", stderr })
+        }
+    }
+
+    fn lookup_remote_url(&self, remote: &str) -> Result<String, GitError> {
+        let key = format!("remote.{}.url", remote);
+        let output = self.run(&["config", "--get", &key], "git config")?;
+
+        match output.status.code() {
+            Some(0) => Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned()),
+            // the key is not set
+            Some(1) => Err(GitError::NotAvailable),
+            _ => {
+                let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+                Err(GitError::Failed { command: "git config", stderr })
+            }
         }
     }
 }
 
-fn parse_git_info(stdout: &[u8]) -> Result<GitInfo, WithNotAvailableVariant<ErrorMessage>> {
+fn parse_git_info(stdout: &[u8]) -> Result<GitInfo, GitError> {
 
     let mut branch = String::new();
     let mut has_untracked = false;
@@ -48,7 +91,7 @@ fn parse_git_info(stdout: &[u8]) -> Result<GitInfo, WithNotAvailableVariant<Erro
     let mut first = true;
 
     for line in stdout.lines() {
-        let line = line?;
+        let line = line.map_err(|err| GitError::InvalidOutput(format!("invalid `git status -sb` output: {}", err)))?;
 
         if first {
             first = false;
@@ -76,9 +119,9 @@ fn parse_git_info(stdout: &[u8]) -> Result<GitInfo, WithNotAvailableVariant<Erro
     })
 }
 
-fn parse_branch(mut line: String) -> Result<String, ErrorMessage> {
+fn parse_branch(mut line: String) -> Result<String, GitError> {
     if !line.starts_with("## ") {
-        return Err(ErrorMessage::new(format!("invalid head `git status -sb` line: {}", line)));
+        return Err(GitError::InvalidOutput(format!("invalid head `git status -sb` line: {}", line)));
     }
 
     let end_branch_name_idx = position_triple_dot(&line)
@@ -111,14 +154,11 @@ fn position_triple_dot(line: &str) -> Option<usize> {
 }
 
 /// Returns (has_untracked, has_unstaged, has_staged)
-fn parse_status_line(line: impl AsRef<str>) -> Result<(bool, bool, bool), ErrorMessage> {
+fn parse_status_line(line: impl AsRef<str>) -> Result<(bool, bool, bool), GitError> {
     let line = line.as_ref();
 
-    if line.len() < 3 {
-        return Err(ErrorMessage::new(format!("invalid `git status -sb` line: {}", line)));
-    }
-    if line.as_bytes()[2] != b' ' {
-        return Err(ErrorMessage::new(format!("invalid `git status -sb` line: {}", line)));
+    if line.len() < 3 || line.as_bytes()[2] != b' ' {
+        return Err(GitError::InvalidOutput(format!("invalid `git status -sb` line: {}", line)));
     }
     let line = &line.as_bytes()[0..2];
 
@@ -1,36 +1,70 @@
-use std::env;
-use crate::iface::{ColumnCountPlugin, ErrorMessage};
+use std::{
+    ffi::{OsString, CStr},
+    os::raw::c_char
+};
+
+use libc;
+
+use crate::{
+    iface::ColumnCountPlugin,
+    error::ColumnCountError
+};
 
 
 pub mod git;
 pub mod cwd;
 pub mod terminal;
+pub mod powerline;
+pub mod errors;
+mod term_writer;
+pub(crate) mod command;
 
 pub use self::{
     git::Git,
     cwd::CwdPath,
-    terminal::Terminal
+    terminal::Terminal,
+    powerline::PowerlineTerminal
 };
 
 
 
-pub struct ColumnCount;
+/// Returns the host name of this machine.
+pub(crate) fn hostname() -> Option<String> {
+    let mut buf = [0 as c_char; 256];
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) };
+    if res != 0 {
+        return None;
+    }
+    // gethostname doesn't guarantee null termination on truncation
+    buf[buf.len() - 1] = 0;
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Column count passed in as (command line) argument.
+#[derive(Debug)]
+pub struct ColumnCount {
+    arg: Option<OsString>
+}
+
+impl ColumnCount {
+    pub fn new(arg: Option<OsString>) -> Self {
+        ColumnCount { arg }
+    }
+}
 
 impl ColumnCountPlugin for ColumnCount {
-    fn get_column_count() -> Result<usize, ErrorMessage> {
-        let first_relevant_arg = env::args_os().skip(1).next();
-        if let Some(os_arg) = first_relevant_arg {
+    fn get_column_count(&self) -> Result<usize, ColumnCountError> {
+        if let Some(ref os_arg) = self.arg {
             if let Some(str_arg) = os_arg.to_str() {
                 if let Ok(count) = str_arg.parse() {
                     return Ok(count);
                 }
             }
 
-            let err = format!("invalid column count arg: {}", os_arg.to_string_lossy());
-            Err(ErrorMessage::new(err))
+            Err(ColumnCountError::Invalid(os_arg.clone()))
         } else {
-            let err = "missing column count argument";
-            Err(ErrorMessage::new(err))
+            Err(ColumnCountError::Missing)
         }
     }
 }
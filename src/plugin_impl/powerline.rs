@@ -0,0 +1,403 @@
+use std::{
+    env,
+    io::{self, Write},
+    ops::Range,
+    path::{Path, PathBuf}
+};
+
+use unicode_width::UnicodeWidthStr;
+use terminfo::Database;
+
+use crate::{
+    error::PluginError,
+    iface::{TerminalPlugin, FormatLike, SegmentLayout, RenderTarget, RenderKind, TerminalOptions}
+};
+
+use super::{
+    errors::ErrorSegments,
+    term_writer::{
+        TermWriter, Color, color, supports_hyperlinks, load_terminfo, has_control_chars,
+        PROMPT_START, PROMPT_END, ERR_START, SELECT_ENDING
+    }
+};
+
+
+struct Separators {
+    /// Drawn where the background color changes.
+    hard: char,
+    /// Drawn between segments with the same background color.
+    thin: char,
+    /// Like `hard` but pointing to the left, used by the right prompt.
+    hard_left: char,
+    thin_left: char
+}
+
+const GLYPHS: Separators = Separators {
+    hard: '\u{e0b0}',
+    thin: '\u{e0b1}',
+    hard_left: '\u{e0b2}',
+    thin_left: '\u{e0b3}'
+};
+
+const ASCII: Separators = Separators {
+    hard: '>',
+    thin: '|',
+    hard_left: '<',
+    thin_left: '|'
+};
+
+/// Returns the foreground and background color.
+fn fmt_to_colors(fmt: FormatLike) -> (Color, Color) {
+    use self::FormatLike::*;
+
+    match fmt {
+        Text => (color::TEXT_WHITE, color::LESS_DARK_GRAY),
+        PrimaryText => (color::BLACK, color::JUNGLE_GREEN),
+        Lines => (color::LIGHT_GRAY, color::DARK_GRAY),
+        SoftWarning => (color::BLACK, color::ORANGE),
+        HardWarning => (color::WHITE, color::SIGNALING_RED),
+        Error => (color::WHITE, color::RED),
+        ExplicitOk => (color::BLACK, color::BRIGHT_GREEN),
+        Hidden => (color::LESS_LIGHT_GRAY, color::DARK_GRAY),
+        RepoName => (color::BLACK, color::CYAN),
+        PathAlias => (color::BLACK, color::YELLOW),
+        SymlinkTarget => (color::LIGHT_GRAY, color::LESS_DARK_GRAY),
+        EscapedBytes => (color::BRIGHT_RED, color::LESS_DARK_GRAY)
+    }
+}
+
+fn bg_of(fmt: FormatLike) -> Color {
+    fmt_to_colors(fmt).1
+}
+
+/// Terminal rendering the segments as blocks with a background color, separated by arrows.
+///
+/// Right aligned segments are rendered after the other segments and the
+/// layout options of the box style are ignored.
+#[derive(Debug)]
+pub struct PowerlineTerminal {
+    column_count: usize,
+    blocks: Vec<Block>,
+    error_segments: ErrorSegments,
+    terminfo: Database,
+    options: TerminalOptions,
+    target: RenderTarget,
+    cwd: Option<PathBuf>,
+    title: Option<String>,
+    hyperlinks: bool,
+    ascii: bool
+}
+
+impl TerminalPlugin for PowerlineTerminal {
+    fn new(column_count: usize, options: &TerminalOptions, target: &RenderTarget) -> Self {
+        let terminfo = load_terminfo();
+        PowerlineTerminal {
+            column_count,
+            blocks: Vec::new(),
+            error_segments: ErrorSegments::new(&options.errors),
+            terminfo,
+            options: options.clone(),
+            target: *target,
+            cwd: None,
+            title: None,
+            hyperlinks: options.hyperlinks.unwrap_or_else(supports_hyperlinks),
+            ascii: options.ascii.unwrap_or_else(|| !is_utf8_locale())
+        }
+    }
+
+    fn column_count(&self) -> usize {
+        self.column_count
+    }
+
+    fn begin_segment(&mut self, _layout: &SegmentLayout) {}
+
+    fn add_text_segment(&mut self, text: &str, fmt_args: FormatLike) {
+        self.add_text_segment_parts(&[(text, fmt_args)]);
+    }
+
+    fn add_text_segment_parts(&mut self, parts: &[(&str, FormatLike)]) {
+        self.blocks.push(Block::new(parts, false));
+    }
+
+    fn extend_previous_segment(&mut self, text: &str, fmt_args: FormatLike) {
+        let extends_previous = !self.blocks.is_empty();
+        self.blocks.push(Block::new(&[(text, fmt_args)], extends_previous));
+    }
+
+    fn add_error_segment(&mut self, scope: &'static str, msg: &str) {
+        self.error_segments.add(scope, msg);
+    }
+
+    fn add_error(&mut self, scope: &'static str, err: &dyn PluginError) {
+        self.error_segments.add_error(scope, err);
+    }
+
+    fn link_previous_segment(&mut self, url: &str) {
+        if !self.hyperlinks || has_control_chars(url) {
+            return;
+        }
+        if let Some(last) = self.blocks.last_mut() {
+            last.link = Some(url.to_owned());
+        }
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = Some(title.to_owned());
+    }
+
+    fn set_cwd(&mut self, cwd: &Path) {
+        self.cwd = Some(cwd.to_owned());
+    }
+
+    fn flush_to_stdout(&self, prompt_ending: &str) {
+        let stdout = io::stdout();
+        let mut term = TermWriter::new(&self.terminfo, &self.target, stdout.lock());
+        let blocks = self.blocks.iter()
+            .filter(|block| !block.parts.is_empty())
+            .collect::<Vec<_>>();
+
+        match self.target.kind {
+            RenderKind::Prompt => {},
+            RenderKind::Right => {
+                let errors = self.error_blocks();
+                let blocks = blocks.into_iter().chain(errors.iter()).collect::<Vec<_>>();
+                self.render_blocks(&mut term, &blocks, true);
+                term.flush().unwrap();
+                return;
+            },
+            kind => {
+                term.fg(color::LIGHT_GRAY);
+                match kind {
+                    RenderKind::Continuation => {
+                        let padding = prompt_ending.width().saturating_sub(1);
+                        write!(term, "{}{:padding$}", self.separators().thin, "", padding=padding).unwrap();
+                    },
+                    RenderKind::Select => write!(term, "{}", SELECT_ENDING).unwrap(),
+                    _ => write!(term, "{}", prompt_ending).unwrap()
+                }
+                term.reset_fmt();
+                term.flush().unwrap();
+                return;
+            }
+        }
+
+        self.error_segments.save();
+        let badge = self.error_badge();
+        let blocks = blocks.into_iter().chain(badge.iter()).collect::<Vec<_>>();
+
+        if let Some(title) = &self.title {
+            term.title(title);
+        }
+        if let (true, Some(cwd)) = (self.options.osc7, &self.cwd) {
+            term.osc7(cwd);
+        }
+        if self.options.semantic_prompt {
+            term.non_printing(PROMPT_START);
+        }
+
+        for line in layout_lines(self.column_count, &blocks) {
+            self.render_blocks(&mut term, &blocks[line], false);
+            writeln!(term).unwrap();
+        }
+
+        for error in self.error_segments.full() {
+            term.fg(fmt_to_colors(error.format()).1);
+            writeln!(term, "{} {}: {}", ERR_START, error.scope, error.text()).unwrap();
+        }
+
+        term.fg(color::LIGHT_GRAY);
+        write!(term, "{}", prompt_ending).unwrap();
+        term.reset_fmt();
+        if self.options.semantic_prompt {
+            term.non_printing(PROMPT_END);
+        }
+        term.flush().unwrap();
+    }
+}
+
+impl PowerlineTerminal {
+
+    fn separators(&self) -> &'static Separators {
+        if self.ascii { &ASCII } else { &GLYPHS }
+    }
+
+    /// The badge replacing the errors in the compact error display.
+    fn error_badge(&self) -> Option<Block> {
+        let badge = self.error_segments.badge()?;
+        Some(Block::new(&[(&badge, FormatLike::Error)], false))
+    }
+
+    /// The errors as blocks, or the badge replacing them.
+    fn error_blocks(&self) -> Vec<Block> {
+        self.error_segments.full().iter()
+            .map(|error| {
+                let text = error.text().split_whitespace().collect::<Vec<_>>().join(" ");
+                let text = format!("{} {}: {}", ERR_START, error.scope, text);
+                Block::new(&[(&text, error.format())], false)
+            })
+            .chain(self.error_badge())
+            .collect()
+    }
+
+    /// Renders given blocks on a single line, with arrows pointing left if `left_pointing`.
+    fn render_blocks<W>(&self, term: &mut TermWriter<W>, blocks: &[&Block], left_pointing: bool)
+        where W: Write
+    {
+        let separators = self.separators();
+        let mut prev_bg = None;
+        for block in blocks {
+            for (idx, (text, fmt)) in block.parts.iter().enumerate() {
+                let (fg, bg) = fmt_to_colors(*fmt);
+                let is_first = idx == 0;
+                if left_pointing {
+                    if prev_bg != Some(bg) {
+                        term.reset_fmt();
+                        if let Some(prev_bg) = prev_bg {
+                            term.bg(prev_bg);
+                        }
+                        term.fg(bg);
+                        write!(term, "{}", separators.hard_left).unwrap();
+                    } else if is_first {
+                        term.fg(color::LIGHT_GRAY);
+                        write!(term, "{}", separators.thin_left).unwrap();
+                    }
+                } else {
+                    match prev_bg {
+                        Some(prev_bg) if prev_bg != bg => {
+                            term.fg(prev_bg);
+                            term.bg(bg);
+                            write!(term, "{}", separators.hard).unwrap();
+                        },
+                        Some(_) if is_first => {
+                            term.fg(color::LIGHT_GRAY);
+                            write!(term, "{}", separators.thin).unwrap();
+                        },
+                        _ => {}
+                    }
+                }
+
+                term.fg(fg);
+                term.bg(bg);
+                if is_first {
+                    write!(term, " ").unwrap();
+                    if let Some(link) = &block.link {
+                        term.begin_link(link);
+                    }
+                }
+                write!(term, "{}", text).unwrap();
+                if idx + 1 == block.parts.len() {
+                    if block.link.is_some() {
+                        term.end_link();
+                    }
+                    write!(term, " ").unwrap();
+                }
+                prev_bg = Some(bg);
+            }
+        }
+
+        term.reset_fmt();
+        if let (false, Some(prev_bg)) = (left_pointing, prev_bg) {
+            term.fg(prev_bg);
+            write!(term, "{}", separators.hard).unwrap();
+            term.reset_fmt();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Block {
+    parts: Vec<(String, FormatLike)>,
+    /// Url the block links to.
+    link: Option<String>,
+    /// Blocks extending the previous one are kept on the same line.
+    extends_previous: bool
+}
+
+impl Block {
+
+    fn new(parts: &[(&str, FormatLike)], extends_previous: bool) -> Self {
+        Block {
+            parts: parts.iter().map(|(text, fmt)| (text.to_string(), *fmt)).collect(),
+            link: None,
+            extends_previous
+        }
+    }
+
+    /// Returns the number of columns the block takes up, without the separator after it.
+    fn width(&self) -> usize {
+        let text_width = self.parts.iter().map(|(text, _)| text.width()).sum::<usize>();
+        // an arrow is drawn where the background color changes
+        let bg_changes = self.parts.windows(2)
+            .filter(|parts| bg_of(parts[0].1) != bg_of(parts[1].1))
+            .count();
+        // +2 for the space before and after the text
+        text_width + bg_changes + 2
+    }
+}
+
+/// Splits the blocks into lines, moving blocks (and the blocks extending them)
+/// to the next line if they don't fit.
+fn layout_lines(column_count: usize, blocks: &[&Block]) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut width = 0;
+    let mut idx = 0;
+    while idx < blocks.len() {
+        let end = idx + 1 + blocks[idx + 1..].iter()
+            .take_while(|block| block.extends_previous)
+            .count();
+        // +1 for the separator after each block
+        let group_width = blocks[idx..end].iter()
+            .map(|block| block.width() + 1)
+            .sum::<usize>();
+        if idx > start && width + group_width > column_count {
+            lines.push(start..idx);
+            start = idx;
+            width = 0;
+        }
+        width += group_width;
+        idx = end;
+    }
+    if start < blocks.len() {
+        lines.push(start..blocks.len());
+    }
+    lines
+}
+
+/// Returns true if the locale uses UTF-8, else the powerline glyphs can't be shown.
+fn is_utf8_locale() -> bool {
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"].iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default()
+        .to_lowercase();
+    locale.contains("utf-8") || locale.contains("utf8")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn block_width_includes_padding_and_inner_arrows() {
+        let block = Block::new(&[("repo", FormatLike::RepoName), ("/src", FormatLike::Text)], false);
+        assert_eq!(block.width(), 4 + 4 + 1 + 2);
+        let block = Block::new(&[("~/", FormatLike::Text), ("src", FormatLike::SymlinkTarget)], false);
+        assert_eq!(block.width(), 5 + 2);
+    }
+
+    #[test]
+    fn extending_blocks_are_kept_on_the_same_line() {
+        let blocks = [
+            Block::new(&[("aaaa", FormatLike::Text)], false),
+            Block::new(&[("bbbb", FormatLike::Text)], false),
+            Block::new(&[("cc", FormatLike::Text)], true),
+            Block::new(&[("dddd", FormatLike::Text)], false)
+        ];
+        let blocks = blocks.iter().collect::<Vec<_>>();
+        // blocks take 7 columns (with separator), `cc` 5
+        assert_eq!(layout_lines(26, &blocks), vec![0..4]);
+        assert_eq!(layout_lines(25, &blocks), vec![0..3, 3..4]);
+        assert_eq!(layout_lines(18, &blocks), vec![0..1, 1..3, 3..4]);
+    }
+}
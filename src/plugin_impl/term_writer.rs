@@ -0,0 +1,247 @@
+//! Output helpers shared by the terminal plugins.
+use std::{
+    env,
+    io::{self, Write},
+    path::Path
+};
+
+use terminfo::{expand, Database, capability as cap};
+
+use crate::{
+    iface::{Shell, RenderKind, RenderTarget},
+    url
+};
+
+/// OSC 133 (FinalTerm) marks for the start of the prompt and the start of the command input.
+pub(super) const PROMPT_START: &str = "\x1b]133;A\x07";
+pub(super) const PROMPT_END: &str = "\x1b]133;B\x07";
+/// Starts the text of an error.
+pub(super) const ERR_START: &str = "!!";
+/// Replaces the prompt ending in the prompt of `select`.
+pub(super) const SELECT_ENDING: &str = "#? ";
+
+pub(super) type Color = u8;
+
+pub(super) mod color {
+    #![allow(unused)]
+    use super::Color;
+
+    pub const BLACK: Color = 16;
+    pub const WHITE: Color = 231;
+    pub const TEXT_WHITE: Color = 251;
+    pub const CYAN: Color = 6;
+    pub const YELLOW: Color = 3;
+    pub const RED: Color = 1;
+    pub const BRIGHT_RED: Color = 9;
+    pub const BRIGHT_GREEN: Color = 10;
+    pub const DARK_GRAY: Color = 236;
+    pub const LESS_DARK_GRAY: Color = 238;
+    pub const LIGHT_GRAY: Color = 243;
+    pub const LESS_LIGHT_GRAY: Color = 240;
+    pub const JUNGLE_GREEN: Color = 112;
+    pub const ORANGE: Color = 208;
+    pub const SIGNALING_RED: Color = 196;
+}
+
+/// Loads the terminfo entry of `$TERM`, if there is none (e.g. for an unknown
+/// `$TERM`) an empty one is used, i.e. no colors.
+pub(super) fn load_terminfo() -> Database {
+    Database::from_env().unwrap_or_else(|_| {
+        let mut builder = Database::new();
+        builder.name("unknown").description("no terminfo entry");
+        builder.build().expect("name and description are set")
+    })
+}
+
+/// Returns true if `text` contains control chars, which would end the escape
+/// sequence of a title or link early.
+pub(super) fn has_control_chars(text: &str) -> bool {
+    text.chars().any(char::is_control)
+}
+
+/// Writes to the prompt, escaping the text for the shell the prompt is for.
+pub(super) struct TermWriter<'a, W: Write+'a> {
+    terminfo: &'a Database,
+    shell: Shell,
+    /// The markers around text which doesn't take up any space, if the shell needs them.
+    non_printing_markers: Option<(&'static [u8], &'static [u8])>,
+    out: W
+}
+
+impl<'a, W: 'a> TermWriter<'a, W>
+    where W: Write
+{
+    pub(super) fn new(terminfo: &'a Database, target: &RenderTarget, out: W) -> Self {
+        let non_printing_markers =
+            match (target.shell, target.kind) {
+                // bash prints `PS3` as is, so the markers would end up in the output
+                (Shell::Bash, RenderKind::Select) => None,
+                (Shell::Bash, _) => Some((&b"\x01"[..], &b"\x02"[..])),
+                (Shell::Zsh, _) => Some((&b"%{"[..], &b"%}"[..]))
+            };
+        TermWriter { terminfo, shell: target.shell, non_printing_markers, out }
+    }
+
+    /// Writes `text` marked as not taking up any space, so that the shell
+    /// calculates the prompt length correctly.
+    pub(super) fn non_printing(&mut self, text: &str) {
+        self.begin_non_printing();
+        write!(self, "{}", text).unwrap();
+        self.end_non_printing();
+    }
+
+    fn begin_non_printing(&mut self) {
+        if let Some((begin, _)) = self.non_printing_markers {
+            self.out.write_all(begin).unwrap();
+        }
+    }
+
+    fn end_non_printing(&mut self) {
+        if let Some((_, end)) = self.non_printing_markers {
+            self.out.write_all(end).unwrap();
+        }
+    }
+
+    pub(super) fn fg(&mut self, color: Color) {
+        self.begin_non_printing();
+        if let Some(cap) = self.terminfo.get::<cap::SetAForeground>() {
+            expand!(&mut self.out, cap.as_ref(); color).unwrap();
+        }
+        self.end_non_printing();
+    }
+
+    pub(super) fn bg(&mut self, color: Color) {
+        self.begin_non_printing();
+        if let Some(cap) = self.terminfo.get::<cap::SetABackground>() {
+            expand!(&mut self.out, cap.as_ref(); color).unwrap();
+        }
+        self.end_non_printing();
+    }
+
+    pub(super) fn reset_fmt(&mut self) {
+        self.begin_non_printing();
+        let terminfo = self.terminfo;
+        if let Some(cap) = terminfo.get::<cap::ExitAttributeMode>() {
+            expand!(&mut self.out, cap.as_ref();).unwrap();
+        } else if let Some(cap) = terminfo.get::<cap::SetAttributes>() {
+            expand!(&mut self.out, cap.as_ref(); 0).unwrap();
+        } else if let Some(cap) = terminfo.get::<cap::OrigPair>() {
+            expand!(&mut self.out, cap.as_ref();).unwrap()
+        }
+        self.end_non_printing();
+    }
+
+    /// Sets the window title (without control chars), using the status line capabilities
+    /// if the terminal has them.
+    pub(super) fn title(&mut self, title: &str) {
+        let title =
+            if has_control_chars(title) {
+                title.replace(char::is_control, "")
+            } else {
+                title.to_owned()
+            };
+        let terminfo = self.terminfo;
+        let has_status_line = terminfo.get::<cap::HasStatusLine>().map(|cap| cap.0).unwrap_or(false);
+        let mut seq = Vec::new();
+        match (has_status_line, terminfo.get::<cap::ToStatusLine>(), terminfo.get::<cap::FromStatusLine>()) {
+            (true, Some(to), Some(from)) => {
+                expand!(&mut seq, to.as_ref(); 0).unwrap();
+                seq.extend_from_slice(title.as_bytes());
+                expand!(&mut seq, from.as_ref();).unwrap();
+            },
+            _ => seq.extend_from_slice(format!("\x1b]0;{}\x07", title).as_bytes())
+        }
+        self.non_printing(&String::from_utf8_lossy(&seq));
+    }
+
+    /// Reports the cwd as `file://` url, e.g. so that new tabs open in the same directory.
+    pub(super) fn osc7(&mut self, cwd: &Path) {
+        self.non_printing(&format!("\x1b]7;{}\x07", url::file_url(cwd)));
+    }
+
+    /// Starts an OSC 8 hyperlink, which is ended with [`TermWriter::end_link`].
+    pub(super) fn begin_link(&mut self, url: &str) {
+        self.non_printing(&format!("\x1b]8;;{}\x07", url));
+    }
+
+    pub(super) fn end_link(&mut self) {
+        self.non_printing("\x1b]8;;\x07");
+    }
+}
+
+impl<'a, W: 'a> Write for TermWriter<'a, W>
+    where W: Write
+{
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.out.flush()
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        match self.shell {
+            Shell::Bash => self.out.write(buf),
+            Shell::Zsh => {
+                // zsh expands `%` sequences in the prompt
+                for chunk in buf.split_inclusive(|byte| *byte == b'%') {
+                    self.out.write_all(chunk)?;
+                    if chunk.ends_with(b"%") {
+                        self.out.write_all(b"%")?;
+                    }
+                }
+                Ok(buf.len())
+            }
+        }
+    }
+}
+
+/// Guesses if the terminal supports OSC 8 hyperlinks, terminals which don't
+/// might print the escape sequence as text.
+pub(super) fn supports_hyperlinks() -> bool {
+    let var = |name| env::var(name).unwrap_or_default();
+
+    let known_program = ["iTerm.app", "WezTerm", "vscode", "ghostty", "Hyper"]
+        .contains(&var("TERM_PROGRAM").as_str());
+    let vte_with_links = var("VTE_VERSION").parse::<u32>().map(|version| version >= 5000).unwrap_or(false);
+    let known_env = ["KITTY_WINDOW_ID", "WT_SESSION", "KONSOLE_VERSION", "DOMTERM"].iter()
+        .any(|name| env::var_os(name).is_some());
+
+    known_program || vte_with_links || known_env || var("TERM") == "xterm-kitty"
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn empty_terminfo() -> Database {
+        let mut builder = Database::new();
+        builder.name("dumb").description("no capabilities");
+        builder.build().unwrap()
+    }
+
+    fn write_non_printing(target: RenderTarget) -> Vec<u8> {
+        let terminfo = empty_terminfo();
+        let mut out = Vec::new();
+        TermWriter::new(&terminfo, &target, &mut out).non_printing("\x1b[0m");
+        out
+    }
+
+    #[test]
+    fn bash_select_prompt_has_no_non_printing_markers() {
+        let select = RenderTarget { shell: Shell::Bash, kind: RenderKind::Select };
+        let out = write_non_printing(select);
+        assert!(!out.contains(&b'\x01') && !out.contains(&b'\x02'));
+        assert_eq!(out, b"\x1b[0m");
+
+        assert_eq!(write_non_printing(RenderTarget::default()), b"\x01\x1b[0m\x02");
+        let zsh_select = RenderTarget { shell: Shell::Zsh, kind: RenderKind::Select };
+        assert_eq!(write_non_printing(zsh_select), b"%{\x1b[0m%}");
+    }
+
+    #[test]
+    fn control_chars_are_removed_from_titles() {
+        let terminfo = empty_terminfo();
+        let mut out = Vec::new();
+        TermWriter::new(&terminfo, &RenderTarget::default(), &mut out).title("main\x1b\x07!");
+        assert_eq!(out, b"\x01\x1b]0;main!\x07\x02");
+    }
+}
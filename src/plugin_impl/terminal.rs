@@ -1,17 +1,32 @@
 use std::{
     io::{self, Write},
     ops::Range,
-    cmp::min,
-    iter::Peekable
+    cmp::{min, max},
+    iter::{self, Peekable},
+    path::{Path, PathBuf},
+    slice
 };
 
 use crate::{
-    iface::{TerminalPlugin, FormatLike},
+    error::PluginError,
+    iface::{
+        TerminalPlugin, FormatLike, SegmentLayout, Alignment, RenderTarget, RenderKind,
+        TerminalOptions, RightOverflow, PromptLayout
+    },
     config
 };
 
+use super::{
+    errors::ErrorSegments,
+    term_writer::{
+        TermWriter, Color, color, supports_hyperlinks, load_terminfo, has_control_chars,
+        PROMPT_START, PROMPT_END, ERR_START, SELECT_ENDING
+    }
+};
+
 use smallvec::{smallvec, SmallVec};
-use terminfo::{expand, Database, capability as cap};
+use unicode_width::UnicodeWidthStr;
+use terminfo::Database;
 
 // pub const CORNER_SW: char = '╗';
 const CORNER_SE: char = '╔';
@@ -24,27 +39,7 @@ const CORNER_NS: char = '║';
 // pub const ERROR_END: char = '!';
 // pub const CORNER_NW: char = '╝';
 const CORNER_NE: char = '╚';
-const ERR_START: &str = "!!";
-
-
-type Color = u8;
-
-mod color {
-    #![allow(unused)]
-    use super::Color;
-
-    pub const TEXT_WHITE: Color = 251;
-    pub const CYAN: Color = 6;
-    pub const YELLOW: Color = 3;
-    pub const RED: Color = 1;
-    pub const BRIGHT_RED: Color = 9;
-    pub const BRIGHT_GREEN: Color = 10;
-    pub const LIGHT_GRAY: Color = 243;
-    pub const LESS_LIGHT_GRAY: Color = 240;
-    pub const JUNGLE_GREEN: Color = 112;
-    pub const ORANGE: Color = 208;
-    pub const SIGNALING_RED: Color = 196;
-}
+
 
 fn fmt_to_color(fmt: FormatLike) -> Color {
     use self::FormatLike::*;
@@ -57,67 +52,235 @@ fn fmt_to_color(fmt: FormatLike) -> Color {
         HardWarning => color::SIGNALING_RED,
         Error => color::RED,
         ExplicitOk => color::BRIGHT_GREEN,
-        Hidden => color::LESS_LIGHT_GRAY
+        Hidden => color::LESS_LIGHT_GRAY,
+        RepoName => color::CYAN,
+        PathAlias => color::YELLOW,
+        SymlinkTarget => color::LIGHT_GRAY,
+        EscapedBytes => color::BRIGHT_RED
+    }
+}
+
+
+
+const ELLIPSIS: char = '…';
+
+/// Segments which are rendered next to each other, e.g. a segment and its extensions.
+#[derive(Debug, Clone)]
+struct SegmentGroup {
+    segments: SmallVec<[TextSegment; 2]>,
+    /// Groups with a lower priority are dropped first if the prompt has too many lines.
+    priority: i64
+}
+
+impl SegmentGroup {
+
+    fn new(segment: TextSegment, priority: i64) -> Self {
+        SegmentGroup { segments: smallvec![segment], priority }
+    }
+
+    /// Returns the group truncated to fit into `max_len` columns (including the `⟦⟧`).
+    fn truncated(&self, max_len: usize) -> SegmentGroup {
+        let mut rem_len = max_len;
+        let mut segments = SmallVec::new();
+        for segment in &self.segments {
+            // +2 as in TEXT_START(char) + TEXT_END(char)
+            let len = segment.pre_calculated_length + 2;
+            if len <= rem_len {
+                rem_len -= len;
+                segments.push(segment.clone());
+                continue;
+            }
+            // at least the `…` has to fit
+            if rem_len >= 3 {
+                segments.push(segment.truncated(rem_len - 2));
+            }
+            break;
+        }
+        SegmentGroup { segments, priority: self.priority }
     }
 }
 
+impl<'a> IntoIterator for &'a SegmentGroup {
+    type Item = &'a TextSegment;
+    type IntoIter = slice::Iter<'a, TextSegment>;
 
+    fn into_iter(self) -> Self::IntoIter {
+        self.segments.iter()
+    }
+}
 
 #[derive(Debug)]
 pub struct Terminal {
     column_count: usize,
-    text_segments: SmallVec<[SmallVec<[TextSegment; 2]>; 2]>,
-    error_segments: Vec<(&'static str, String)>,
+    text_segments: SmallVec<[SegmentGroup; 2]>,
+    right_segments: SmallVec<[SegmentGroup; 2]>,
+    /// Alignment of the segments currently added.
+    align: Alignment,
+    /// Priority of the segments currently added.
+    priority: i64,
+    error_segments: ErrorSegments,
     terminfo: Database,
+    options: TerminalOptions,
+    target: RenderTarget,
+    cwd: Option<PathBuf>,
+    title: Option<String>,
+    hyperlinks: bool
 }
 
 
 
 impl TerminalPlugin for Terminal {
-    fn new(column_count: usize) -> Self {
-        let terminfo = Database::from_env().unwrap();
+    fn new(column_count: usize, options: &TerminalOptions, target: &RenderTarget) -> Self {
+        let terminfo = load_terminfo();
         Terminal {
             column_count,
             text_segments: Default::default(),
-            error_segments: Default::default(),
-            terminfo
+            right_segments: Default::default(),
+            align: Alignment::Left,
+            priority: 0,
+            error_segments: ErrorSegments::new(&options.errors),
+            terminfo,
+            options: options.clone(),
+            target: *target,
+            cwd: None,
+            title: None,
+            hyperlinks: options.hyperlinks.unwrap_or_else(supports_hyperlinks)
         }
     }
 
+    fn column_count(&self) -> usize {
+        self.column_count
+    }
+
+    fn begin_segment(&mut self, layout: &SegmentLayout) {
+        self.align = layout.align;
+        self.priority = layout.priority;
+    }
+
     fn add_text_segment(&mut self, text: &str, fmt_args: FormatLike) {
-        self.text_segments.push(smallvec![TextSegment::new(text, fmt_args)]);
+        let group = SegmentGroup::new(TextSegment::new(text, fmt_args), self.priority);
+        self.current_segments().push(group);
+    }
+
+    fn add_text_segment_parts(&mut self, parts: &[(&str, FormatLike)]) {
+        let group = SegmentGroup::new(TextSegment::from_parts(parts), self.priority);
+        self.current_segments().push(group);
     }
 
     fn add_error_segment(&mut self, scope: &'static str, msg: &str) {
-        self.error_segments.push((scope, msg.into()));
+        self.error_segments.add(scope, msg);
+    }
+
+    fn add_error(&mut self, scope: &'static str, err: &dyn PluginError) {
+        self.error_segments.add_error(scope, err);
     }
 
     fn extend_previous_segment(&mut self, text: &str, fmt_args: FormatLike) {
         {
-            if let Some(last) = self.text_segments.last_mut() {
-                last.push(TextSegment::new(text, fmt_args));
+            if let Some(last) = self.current_segments().last_mut() {
+                last.segments.push(TextSegment::new(text, fmt_args));
                 return;
             }
         }
         self.add_text_segment(text, fmt_args);
     }
 
+    fn link_previous_segment(&mut self, url: &str) {
+        if !self.hyperlinks || has_control_chars(url) {
+            return;
+        }
+        if let Some(last) = self.current_segments().last_mut().and_then(|group| group.segments.last_mut()) {
+            last.link = Some(url.to_owned());
+        }
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = Some(title.to_owned());
+    }
+
+    fn set_cwd(&mut self, cwd: &Path) {
+        self.cwd = Some(cwd.to_owned());
+    }
+
     fn flush_to_stdout(&self, prompt_ending: &str) {
+        match self.target.kind {
+            RenderKind::Prompt => {},
+            RenderKind::Right => {
+                let stdout = io::stdout();
+                let mut term = self.writer(stdout.lock());
+                self.render_right_prompt(&mut term);
+                term.flush().unwrap();
+                return;
+            },
+            kind => {
+                let stdout = io::stdout();
+                let mut term = self.writer(stdout.lock());
+                term.fmt(FormatLike::Lines);
+                match kind {
+                    RenderKind::Continuation => {
+                        // aligned with the command entered after `╚☛ `
+                        let padding = prompt_ending.width();
+                        write!(term, "{}{:padding$}", CORNER_NS, "", padding=padding).unwrap();
+                    },
+                    RenderKind::Select => write!(term, "{}{}", CORNER_NE, SELECT_ENDING).unwrap(),
+                    _ => write!(term, "{}", prompt_ending).unwrap()
+                }
+                term.reset_fmt();
+                term.flush().unwrap();
+                return;
+            }
+        }
+
         //TODO split into multiple functions
         // - one for outputting text segments
         // - one for outputting error segments
 
-        let layout = self.calculate_layout();
+        self.error_segments.save();
+        let text_segments = self.text_segments.iter().cloned()
+            .chain(self.error_badge())
+            .collect::<Vec<_>>();
+        let text_segments = fit_segments(self.column_count, &text_segments, self.options.max_lines);
+        let right_segments = fit_right_segments(self.column_count, &self.right_segments);
+        let layout = calculate_layout(
+            self.column_count,
+            &text_segments,
+            &right_segments,
+            &self.options
+        );
 
         let stdout = io::stdout();
         let mut term = self.writer(stdout.lock());
 
-        self.render_text_segments(&mut term, layout);
-        self.render_error_segments(&mut term);
+        self.render_title(&mut term);
+        self.render_osc7(&mut term);
+        if self.options.semantic_prompt {
+            term.non_printing(PROMPT_START);
+        }
+
+        // errors need lines of their own
+        let all_segments = text_segments.iter().chain(right_segments.iter());
+        let compact = self.options.layout == PromptLayout::Compact
+            && self.error_segments.full().is_empty()
+            && fits_compact(self.column_count, all_segments.clone(), prompt_ending);
+
+        if compact {
+            for segment_group in all_segments {
+                self.render_segment_group(&mut term, segment_group);
+                write!(term, "{}", LINE).unwrap();
+            }
+            term.fmt(FormatLike::Lines);
+            write!(term, "{}", prompt_ending).unwrap();
+        } else {
+            self.render_text_segments(&mut term, &text_segments, &right_segments, layout);
+            self.render_error_segments(&mut term);
 
-        term.fmt(FormatLike::Lines);
-        write!(term, "{}{}", CORNER_NE, prompt_ending).unwrap();
+            term.fmt(FormatLike::Lines);
+            write!(term, "{}{}", CORNER_NE, prompt_ending).unwrap();
+        }
         term.reset_fmt();
+        if self.options.semantic_prompt {
+            term.non_printing(PROMPT_END);
+        }
         term.flush().unwrap();
     }
 }
@@ -125,11 +288,40 @@ impl TerminalPlugin for Terminal {
 
 impl Terminal {
 
-    fn render_text_segments<W>(&self, term: &mut TermWriter<W>, layout: Vec<LineLayout>)
+    fn current_segments(&mut self) -> &mut SmallVec<[SegmentGroup; 2]> {
+        match self.align {
+            Alignment::Left => &mut self.text_segments,
+            Alignment::Right => &mut self.right_segments
+        }
+    }
+
+    fn render_title<W>(&self, term: &mut TermWriter<W>)
+        where W: Write
+    {
+        if let Some(title) = &self.title {
+            term.title(title);
+        }
+    }
+
+    fn render_osc7<W>(&self, term: &mut TermWriter<W>)
+        where W: Write
+    {
+        if let (true, Some(cwd)) = (self.options.osc7, &self.cwd) {
+            term.osc7(cwd);
+        }
+    }
+
+    fn render_text_segments<W>(
+        &self,
+        term: &mut TermWriter<W>,
+        text_segments: &[SegmentGroup],
+        right_segments: &[SegmentGroup],
+        layout: Vec<LineLayout>
+    )
         where W: Write
     {
         let mut first = true;
-        for LineLayout { segments, join_padding, rem_padding } in layout {
+        for LineLayout { segments, join_padding, rem_padding, right } in layout {
             term.fmt(FormatLike::Lines);
             if first {
                 first = false;
@@ -138,41 +330,103 @@ impl Terminal {
                 write!(term, "{}", CORNER_NSE).unwrap();
             }
 
-            for segment_group in &self.text_segments[segments] {
-                for segment in segment_group {
-                    term.fmt(FormatLike::Lines);
-                    write!(term, "{}", TEXT_START).unwrap();
-                    term.fmt(segment.fmt);
-                    write!(term, "{}", &segment.text).unwrap();
-                    term.fmt(FormatLike::Lines);
-                    write!(term, "{}", TEXT_END).unwrap();
-                }
+            for segment_group in &text_segments[segments] {
+                self.render_segment_group(term, segment_group);
+                term.fmt(FormatLike::Lines);
                 for _ in 0..join_padding {
                     write!(term, "{}", LINE).unwrap();
                 }
             }
 
+            term.fmt(FormatLike::Lines);
             for _ in 0..rem_padding {
                 write!(term, "{}", LINE).unwrap();
             }
-            write!(term, "\n").unwrap();
+
+            if let Some(right) = right {
+                for (idx, segment_group) in right_segments[right].iter().enumerate() {
+                    if idx > 0 {
+                        term.fmt(FormatLike::Lines);
+                        write!(term, "{}", LINE).unwrap();
+                    }
+                    self.render_segment_group(term, segment_group);
+                }
+            }
+            writeln!(term).unwrap();
+        }
+    }
+
+    /// Renders all segments and errors on a single line, without any corners or ending.
+    fn render_right_prompt<W>(&self, term: &mut TermWriter<W>)
+        where W: Write
+    {
+        let mut first = true;
+        let mut separate = |term: &mut TermWriter<W>| {
+            if !first {
+                term.fmt(FormatLike::Lines);
+                write!(term, "{}", LINE).unwrap();
+            }
+            first = false;
+        };
+
+        let segment_groups = self.text_segments.iter().cloned()
+            .chain(self.right_segments.iter().cloned())
+            .chain(self.error_badge());
+        for segment_group in segment_groups {
+            separate(term);
+            self.render_segment_group(term, &segment_group);
+        }
+
+        for error in self.error_segments.full() {
+            separate(term);
+            term.fmt(FormatLike::Lines);
+            write!(term, "{}", TEXT_START).unwrap();
+            term.fmt(error.format());
+            let text = error.text().split_whitespace().collect::<Vec<_>>().join(" ");
+            write!(term, "{} {}: {}", ERR_START, error.scope, text).unwrap();
+            term.fmt(FormatLike::Lines);
+            write!(term, "{}", TEXT_END).unwrap();
+        }
+
+        term.reset_fmt();
+    }
+
+    fn render_segment_group<W>(&self, term: &mut TermWriter<W>, segment_group: &SegmentGroup)
+        where W: Write
+    {
+        for segment in segment_group {
+            term.fmt(FormatLike::Lines);
+            write!(term, "{}", TEXT_START).unwrap();
+            if let Some(link) = &segment.link {
+                term.begin_link(link);
+            }
+            for (text, fmt) in &segment.parts {
+                term.fmt(*fmt);
+                write!(term, "{}", text).unwrap();
+            }
+            if segment.link.is_some() {
+                term.end_link();
+            }
+            term.fmt(FormatLike::Lines);
+            write!(term, "{}", TEXT_END).unwrap();
         }
     }
 
     fn render_error_segments<W>(&self, term: &mut TermWriter<W>)
         where W: Write
     {
-        for (scope, text) in self.error_segments.iter() {
+        for error in self.error_segments.full() {
+            let (scope, text) = (error.scope, error.text());
             term.fmt(FormatLike::Lines);
             write!(term, "{}", CORNER_NSE).unwrap();
-            term.fmt(FormatLike::Error);
-            let mut text = text.trim();
+            term.fmt(error.format());
+            let mut text = &*text;
             write!(term, "{} {}: ", ERR_START, scope).unwrap();
             let bulk_len = 1 + ERR_START.len() + 1 + scope.len() + 2;
-            let mut rem_len = self.column_count.checked_sub(bulk_len).unwrap_or(0);
+            let mut rem_len = self.column_count.saturating_sub(bulk_len);
             loop {
                 if text.len() <= rem_len {
-                    term.fmt(FormatLike::Error);
+                    term.fmt(error.format());
                     write!(term, "{}", text).unwrap();
                     break;
                 } else {
@@ -182,7 +436,7 @@ impl Terminal {
                     text = new_text.trim_start();
                     rem_len = self.column_count - 3;
 
-                    term.fmt(FormatLike::Error);
+                    term.fmt(error.format());
                     write!(term, "{text}", text=line_text.trim_end()).unwrap();
                     term.fmt(FormatLike::Lines);
                     write!(term, "\n{sep}", sep=CORNER_NS).unwrap();
@@ -191,7 +445,7 @@ impl Terminal {
                     }
                 }
             }
-            write!(term, "\n").unwrap();
+            writeln!(term).unwrap();
         }
     }
 }
@@ -218,29 +472,150 @@ fn find_viable_split_idx(text: &str, max_len: usize) -> usize {
 
 impl Terminal {
 
-    fn writer<W>(&self, out: W) -> TermWriter<W>
+    /// The badge replacing the errors in the compact error display, it's never dropped.
+    fn error_badge(&self) -> Option<SegmentGroup> {
+        let badge = self.error_segments.badge()?;
+        Some(SegmentGroup::new(TextSegment::new(&badge, FormatLike::Error), i64::MAX))
+    }
+
+    fn writer<W>(&self, out: W) -> TermWriter<'_, W>
         where W: Write
     {
-        TermWriter {
-            terminal: self,
-            out
-        }
+        TermWriter::new(&self.terminfo, &self.target, out)
     }
 
-    fn calculate_layout(&self) -> Vec<LineLayout> {
-        // -1 as it starts with a `╠` or similar
-        let init_rem_space = self.column_count - 1;
+}
+
+/// Returns true if all segments and the prompt ending fit on a single line,
+/// leaving enough space for the command.
+fn fits_compact<'a>(
+    column_count: usize,
+    segment_groups: impl Iterator<Item=&'a SegmentGroup>,
+    prompt_ending: &str
+) -> bool {
+    // +1 for the `═` after each group
+    let len = segment_groups
+        .map(|group| calc_min_segment_group_len(group) + 1)
+        .sum::<usize>() + prompt_ending.width();
+    len + config::COMPACT_MIN_INPUT_SPACE <= column_count
+}
+
+/// Truncates segment groups which do not fit into a single line and, if the
+/// segments need more than `max_lines` lines, drops the ones with the lowest priority.
+fn fit_segments(column_count: usize, text_segments: &[SegmentGroup], max_lines: Option<usize>)
+    -> Vec<SegmentGroup>
+{
+    // -1 as it starts with a `╠` or similar
+    let max_len = column_count - 1;
+    let mut groups = text_segments.iter()
+        .map(|group| group.truncated(max_len))
+        .collect::<Vec<_>>();
+
+    if let Some(max_lines) = max_lines {
+        let max_lines = max(max_lines, 1);
+        let options = TerminalOptions::default();
+        while calculate_layout(column_count, &groups, &[], &options).len() > max_lines {
+            // from groups with the same priority the later ones are dropped first
+            let (idx, _) = groups.iter().enumerate().rev()
+                .min_by_key(|(_, group)| group.priority)
+                .unwrap();
+            groups.remove(idx);
+        }
+    }
 
-        let mut lines = Vec::new();
-        let mut text_segments = self.text_segments.iter().peekable();
+    groups
+}
 
-        let mut idx_offset = 0;
-        while let Some(line) = calc_next_line_layout(&mut text_segments, init_rem_space, idx_offset) {
-            idx_offset = line.segments.end;
-            lines.push(line)
+/// Truncates the right aligned segments so that they fit into a single line together,
+/// groups for which not even a `⟦…⟧` fits are dropped.
+fn fit_right_segments(column_count: usize, right_segments: &[SegmentGroup]) -> Vec<SegmentGroup> {
+    // -1 as it starts with a `╠` or similar
+    let mut rem_len = column_count - 1;
+    let mut groups = Vec::new();
+    for group in right_segments {
+        // the groups are separated by a `═`
+        let separator_len = if groups.is_empty() { 0 } else { 1 };
+        let group = group.truncated(rem_len.saturating_sub(separator_len));
+        if group.segments.is_empty() {
+            break;
         }
+        rem_len -= separator_len + calc_min_segment_group_len(&group);
+        groups.push(group);
+    }
+    groups
+}
+
+fn calculate_layout(
+    column_count: usize,
+    text_segments: &[SegmentGroup],
+    right_segments: &[SegmentGroup],
+    options: &TerminalOptions
+) -> Vec<LineLayout> {
+    // -1 as it starts with a `╠` or similar
+    let init_rem_space = column_count - 1;
+
+    let mut lines = Vec::new();
+    let mut text_segments_iter = text_segments.iter().peekable();
+
+    let mut idx_offset = 0;
+    while let Some(line) = calc_next_line_layout(&mut text_segments_iter, init_rem_space, idx_offset) {
+        idx_offset = line.segments.end;
+        lines.push(line)
+    }
+
+    if !right_segments.is_empty() {
+        // a line of their own would exceed the max lines
+        let right_overflow =
+            match options.max_lines {
+                Some(max_lines) if lines.len() >= max_lines => RightOverflow::Drop,
+                _ => options.right_overflow
+            };
+        place_right_segments(&mut lines, right_segments, init_rem_space, right_overflow);
+    }
+
+    lines
+}
+
+/// Places the right aligned segments at the end of the first line if they fit,
+/// else they get a line of their own or are dropped.
+fn place_right_segments(
+    lines: &mut Vec<LineLayout>,
+    right_segments: &[SegmentGroup],
+    init_rem_space: usize,
+    right_overflow: RightOverflow
+) {
+    // +1 for the `═` between groups
+    let right_len = right_segments.iter()
+        .map(calc_min_segment_group_len)
+        .sum::<usize>() + right_segments.len() - 1;
+    let right = Some(0..right_segments.len());
+
+    if lines.is_empty() {
+        lines.push(LineLayout { segments: 0..0, join_padding: 0, rem_padding: init_rem_space, right: None });
+    }
 
-        lines
+    let first = &mut lines[0];
+    let nr_items = first.segments.len();
+    let free_space = first.join_padding * nr_items + first.rem_padding;
+    // at least one `═` between the left and right segments
+    if right_len < free_space || (nr_items == 0 && right_len <= free_space) {
+        let (join_padding, rem_padding) =
+            if nr_items == 0 {
+                (0, free_space - right_len)
+            } else {
+                calc_padding(first.segments.start, first.segments.end, free_space - right_len)
+            };
+        first.join_padding = join_padding;
+        first.rem_padding = rem_padding;
+        first.right = right;
+    } else if right_overflow == RightOverflow::Wrap && right_len <= init_rem_space {
+        let end = lines.last().map(|line| line.segments.end).unwrap_or(0);
+        lines.push(LineLayout {
+            segments: end..end,
+            join_padding: 0,
+            rem_padding: init_rem_space - right_len,
+            right
+        });
     }
 }
 
@@ -263,13 +638,14 @@ fn calc_next_line_layout<'a>(
             return Some(LineLayout {
                 segments,
                 join_padding: 0,
-                rem_padding: 0
+                rem_padding: 0,
+                right: None
             });
         }
 
         let mut rem_space = init_rem_space - first_len;
 
-        while let Some(segment_group_iter) = iter.peek().map(|i| *i) {
+        while let Some(segment_group_iter) = iter.peek().copied() {
             let min_len = calc_min_segment_group_len(segment_group_iter);
 
             if rem_space > min_len {
@@ -279,13 +655,13 @@ fn calc_next_line_layout<'a>(
             } else {
                 let segments = first_item..after_last_item;
                 let (join_padding, rem_padding) = calc_padding(first_item, after_last_item, rem_space);
-                return Some(LineLayout { segments, join_padding, rem_padding })
+                return Some(LineLayout { segments, join_padding, rem_padding, right: None })
             }
         }
 
         let segments = first_item..after_last_item;
         let (join_padding, rem_padding) = calc_padding(first_item, after_last_item, rem_space);
-        Some(LineLayout { segments, join_padding, rem_padding })
+        Some(LineLayout { segments, join_padding, rem_padding, right: None })
 }
 
 fn calc_padding(
@@ -305,71 +681,236 @@ fn calc_min_segment_group_len<'a>(group: impl IntoIterator<Item=&'a TextSegment>
     group.into_iter().map(|seg| seg.pre_calculated_length + 2).sum()
 }
 
+#[derive(Debug, PartialEq)]
 struct LineLayout {
     segments: Range<usize>,
     join_padding: usize,
-    rem_padding: usize
-}
-
-struct TermWriter<'a, W: Write+'a> {
-    terminal: &'a Terminal,
-    out: W
+    rem_padding: usize,
+    /// The right aligned segments placed at the end of the line.
+    right: Option<Range<usize>>
 }
 
 impl<'a, W: 'a> TermWriter<'a, W>
     where W: Write
 {
     fn fmt(&mut self, fmt: FormatLike) {
-        write!(&mut self.out, "\x01").unwrap();
-        let color = fmt_to_color(fmt);
-        if let Some(cap) = self.terminal.terminfo.get::<cap::SetAForeground>() {
-            expand!(&mut self.out, cap.as_ref(); color).unwrap();
-        }
-        write!(&mut self.out, "\x02").unwrap();
-    }
-
-    fn reset_fmt(&mut self) {
-        write!(&mut self.out, "\x01").unwrap();
-        let terminfo = &self.terminal.terminfo;
-        if let Some(cap) = terminfo.get::<cap::ExitAttributeMode>() {
-            expand!(&mut self.out, cap.as_ref();).unwrap();
-        } else if let Some(cap) = terminfo.get::<cap::SetAttributes>() {
-            expand!(&mut self.out, cap.as_ref(); 0).unwrap();
-        } else if let Some(cap) = terminfo.get::<cap::OrigPair>() {
-            expand!(&mut self.out, cap.as_ref();).unwrap()
-        }
-        write!(&mut self.out, "\x02").unwrap();
-    }
-}
-
-impl<'a, W: 'a> Write for TermWriter<'a, W>
-    where W: Write
-{
-    fn flush(&mut self) -> Result<(), io::Error> {
-        self.out.flush()
-    }
-
-    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
-        self.out.write(buf)
+        self.fg(fmt_to_color(fmt));
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct TextSegment {
-    text: String,
-    fmt: FormatLike,
+    parts: SmallVec<[(String, FormatLike); 1]>,
     pre_calculated_length: usize,
+    /// Url the segment links to, not included in the length.
+    link: Option<String>
 }
 
 impl TextSegment {
 
     pub fn new(text: impl Into<String>, fmt: FormatLike) -> Self {
         let text = text.into();
-        let len = text.chars().count();
+        let len = text.width();
         TextSegment {
-            text,
-            fmt,
+            parts: smallvec![(text, fmt)],
             pre_calculated_length: len,
+            link: None
+        }
+    }
+
+    pub fn from_parts(parts: &[(&str, FormatLike)]) -> Self {
+        let len = parts.iter().map(|(text, _)| text.width()).sum();
+        TextSegment {
+            parts: parts.iter().map(|(text, fmt)| (text.to_string(), *fmt)).collect(),
+            pre_calculated_length: len,
+            link: None
+        }
+    }
+
+    /// Returns the segment cut to be at most `max_width` wide, ending in a `…` if it was cut.
+    fn truncated(&self, max_width: usize) -> Self {
+        if self.pre_calculated_length <= max_width {
+            return self.clone();
+        }
+
+        let mut rem_width = max_width.saturating_sub(1);
+        let mut parts = SmallVec::<[(String, FormatLike); 1]>::new();
+        for (text, fmt) in &self.parts {
+            let (cut, width) = truncate_to_width(text, *fmt, rem_width);
+            rem_width -= width;
+            parts.push((cut.to_owned(), *fmt));
+            // later parts must not be shown once a part was cut
+            if cut.len() < text.len() || rem_width == 0 {
+                break;
+            }
+        }
+        if let Some((text, _)) = parts.last_mut() {
+            text.push(ELLIPSIS);
+        }
+
+        TextSegment {
+            parts,
+            pre_calculated_length: max_width - rem_width,
+            link: self.link.clone()
+        }
+    }
+}
+
+/// Returns the longest prefix of `text` which is at most `max_width` wide, and its width.
+fn truncate_to_width(text: &str, fmt: FormatLike, max_width: usize) -> (&str, usize) {
+    let mut width = 0;
+    for (idx, unit) in cut_units(text, fmt) {
+        let unit_width = unit.width();
+        if width + unit_width > max_width {
+            return (&text[..idx], width);
         }
+        width += unit_width;
+    }
+    (text, width)
+}
+
+/// Splits `text` into the units it can be cut between, with their index.
+///
+/// These are chars, except for escaped bytes where each escape (e.g. `\xFF`) is kept whole.
+fn cut_units(text: &str, fmt: FormatLike) -> impl Iterator<Item=(usize, &str)> {
+    let mut idx = 0;
+    iter::from_fn(move || {
+        let rest = &text[idx..];
+        let ch = rest.chars().next()?;
+        let len =
+            match fmt {
+                FormatLike::EscapedBytes if rest.starts_with("\\x") => min(4, rest.len()),
+                FormatLike::EscapedBytes if rest.starts_with("\\\\") => 2,
+                _ => ch.len_utf8()
+            };
+        let unit = (idx, &rest[..len]);
+        idx += len;
+        Some(unit)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn groups(texts: &[&str]) -> Vec<SegmentGroup> {
+        texts.iter()
+            .map(|text| SegmentGroup::new(TextSegment::new(*text, FormatLike::Text), 0))
+            .collect()
+    }
+
+    fn wrap() -> TerminalOptions {
+        TerminalOptions::default()
+    }
+
+    fn drop() -> TerminalOptions {
+        TerminalOptions { right_overflow: RightOverflow::Drop, ..Default::default() }
     }
-}
\ No newline at end of file
+
+    fn texts(groups: &[SegmentGroup]) -> Vec<String> {
+        groups.iter()
+            .map(|group| group.segments.iter()
+                .flat_map(|segment| segment.parts.iter().map(|(text, _)| text.as_str()))
+                .collect())
+            .collect()
+    }
+
+    #[test]
+    fn compact_layout_leaves_space_for_the_command() {
+        // 5 + 1 + 4 + 1 + 2 (`☛ `) columns
+        let segments = groups(&["abc", "de"]);
+        assert!(fits_compact(33, segments.iter(), "☛ "));
+        assert!(!fits_compact(32, segments.iter(), "☛ "));
+    }
+
+    #[test]
+    fn too_long_segments_are_truncated() {
+        let fitted = fit_segments(10, &groups(&["abcdefghijkl", "ab"]), None);
+        assert_eq!(texts(&fitted), vec!["abcdef…", "ab"]);
+        assert_eq!(fitted[0].segments[0].pre_calculated_length, 7);
+    }
+
+    #[test]
+    fn truncation_respects_the_width_of_chars() {
+        let segment = TextSegment::new("日本語です", FormatLike::Text);
+        let truncated = segment.truncated(6);
+        assert_eq!(truncated.parts[0].0, "日本…");
+        assert_eq!(truncated.pre_calculated_length, 5);
+
+        // the wide char doesn't fit, so the following parts are dropped
+        let segment = TextSegment::from_parts(&[("a日", FormatLike::Text), ("bc", FormatLike::Error)]);
+        let truncated = segment.truncated(3);
+        let parts = truncated.parts.iter().map(|(text, _)| &**text).collect::<Vec<_>>();
+        assert_eq!(parts, ["a…"]);
+        assert_eq!(truncated.pre_calculated_length, 2);
+    }
+
+    #[test]
+    fn escaped_bytes_are_not_cut() {
+        let segment = TextSegment::from_parts(&[("ab", FormatLike::Text), ("\\xFF\\\\", FormatLike::EscapedBytes)]);
+        let parts = |max_width| segment.truncated(max_width).parts.into_iter()
+            .map(|(text, _)| text)
+            .collect::<Vec<_>>();
+        // `…` is added to the last part, even if nothing of it is left
+        assert_eq!(parts(6), ["ab", "…"]);
+        assert_eq!(parts(7), ["ab", "\\xFF…"]);
+        assert_eq!(parts(8), ["ab", "\\xFF\\\\"]);
+    }
+
+    #[test]
+    fn right_segments_are_truncated_to_fit_a_line() {
+        let fitted = fit_right_segments(10, &groups(&["abcdefghijkl", "xy"]));
+        assert_eq!(texts(&fitted), vec!["abcdef…"]);
+
+        let fitted = fit_right_segments(12, &groups(&["abc", "defghijkl"]));
+        assert_eq!(texts(&fitted), vec!["abc", "de…"]);
+    }
+
+    #[test]
+    fn segments_with_low_priority_are_dropped_first() {
+        let mut segments = groups(&["aaaa", "bbbb", "cccc", "dddd"]);
+        segments[1].priority = -1;
+        segments[3].priority = 1;
+        // each group needs 6 columns, so only 2 fit on a line of 15
+        let fitted = fit_segments(15, &segments, Some(1));
+        assert_eq!(texts(&fitted), vec!["aaaa", "dddd"]);
+        assert_eq!(texts(&fit_segments(15, &segments, None)).len(), 4);
+    }
+
+    #[test]
+    fn right_segments_are_placed_on_the_first_line_if_they_fit() {
+        let layout = calculate_layout(40, &groups(&["abc", "de"]), &groups(&["xyz"]), &wrap());
+        assert_eq!(layout, vec![
+            LineLayout { segments: 0..2, join_padding: 10, rem_padding: 5, right: Some(0..1) }
+        ]);
+    }
+
+    #[test]
+    fn right_segments_wrap_to_a_line_of_their_own() {
+        let left = groups(&["abcdefgh"]);
+        let right = groups(&["xyzuvw", "q"]);
+        let layout = calculate_layout(20, &left, &right, &wrap());
+        assert_eq!(layout, vec![
+            LineLayout { segments: 0..1, join_padding: 9, rem_padding: 0, right: None },
+            LineLayout { segments: 1..1, join_padding: 0, rem_padding: 7, right: Some(0..2) }
+        ]);
+    }
+
+    #[test]
+    fn right_segments_can_be_dropped_if_they_do_not_fit() {
+        let left = groups(&["abcdefgh"]);
+        let right = groups(&["xyzuvw", "q"]);
+        let layout = calculate_layout(20, &left, &right, &drop());
+        assert_eq!(layout, vec![
+            LineLayout { segments: 0..1, join_padding: 9, rem_padding: 0, right: None }
+        ]);
+    }
+
+    #[test]
+    fn right_segments_without_left_segments() {
+        let layout = calculate_layout(20, &[], &groups(&["xyz"]), &drop());
+        assert_eq!(layout, vec![
+            LineLayout { segments: 0..0, join_padding: 0, rem_padding: 14, right: Some(0..1) }
+        ]);
+    }
+}
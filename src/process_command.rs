@@ -0,0 +1,253 @@
+use std::{
+    error::Error,
+    fmt,
+    time::Duration
+};
+
+use serde_json;
+
+use crate::{
+    iface::{TerminalPlugin, FormatLike, ErrorMessage},
+    error::{PluginError, Severity},
+    plugin_impl::command::{run_shell_command, CommandCache, CommandError},
+    segment::Segment
+};
+
+const ERR_SCOPE: &str = "command";
+
+fn default_timeout_ms() -> u64 { 500 }
+
+/// Options of a segment of type `command`.
+///
+/// ```toml
+/// [segment.deploy_env]
+/// type = "command"
+/// command = "cat .deploy-env"
+/// when = "test -f .deploy-env"
+/// timeout_ms = 200
+/// cache_ttl_secs = 30
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandOptions {
+    /// Shell command whose stdout is shown as segment.
+    command: String,
+    /// Shell command which decides if the segment is shown, by exiting with 0.
+    #[serde(default)]
+    when: Option<String>,
+    /// Timeout for each of `command` and `when`.
+    #[serde(default = "default_timeout_ms")]
+    timeout_ms: u64,
+    /// For how long the output of a successful `command` is reused, 0 disables caching.
+    #[serde(default)]
+    cache_ttl_secs: u64
+}
+
+/// A segment showing the output of a user defined shell command.
+///
+/// The stdout of the command is either used as plain text or,
+/// if it starts with `{`, it's parsed as `{"text": "..", "format": ".."}`
+/// object, where format is the snake case name of a `FormatLike` variant.
+pub struct CommandSegment {
+    name: String,
+    options: CommandOptions
+}
+
+impl CommandSegment {
+    pub fn new(name: &str, options: CommandOptions) -> Self {
+        CommandSegment { name: name.to_owned(), options }
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.options.timeout_ms)
+    }
+
+    fn should_show(&self) -> Result<bool, LookupError> {
+        match self.options.when {
+            Some(ref when) => Ok(run_shell_command(when, self.timeout())?.status.success()),
+            None => Ok(true)
+        }
+    }
+
+    fn lookup_output(&self) -> Result<String, LookupError> {
+        let command = &self.options.command;
+        let ttl = Duration::from_secs(self.options.cache_ttl_secs);
+        let cache =
+            if ttl > Duration::from_secs(0) {
+                Some(CommandCache::new(&self.name, command, ttl))
+            } else {
+                None
+            };
+
+        if let Some(output) = cache.as_ref().and_then(|cache| cache.get()) {
+            return Ok(output);
+        }
+
+        let output = run_shell_command(command, self.timeout())?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let msg = format!("`{}` failed with {}: {}", command, output.status, stderr.trim());
+            return Err(ErrorMessage::new(msg).into());
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        if let Some(cache) = cache {
+            // a failing cache only makes things slower, so don't fail the segment
+            let _ = cache.put(&stdout);
+        }
+        Ok(stdout)
+    }
+
+    fn lookup_segment(&self) -> Result<Option<CommandSegmentOutput>, LookupError> {
+        if !self.should_show()? {
+            return Ok(None);
+        }
+        Ok(parse_output(&self.lookup_output()?)?)
+    }
+}
+
+impl<T> Segment<T> for CommandSegment
+    where T: TerminalPlugin
+{
+    fn render(&self, terminal: &mut T) {
+        match self.lookup_segment() {
+            Ok(Some(CommandSegmentOutput { text, format })) => {
+                terminal.add_text_segment(&text, format);
+            },
+            Ok(None) => {},
+            Err(err) => {
+                terminal.add_error(ERR_SCOPE, &SegmentError { name: &self.name, err });
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum LookupError {
+    /// Running the command failed, e.g. it timed out.
+    Command(CommandError),
+    /// The command failed or its output is invalid.
+    Other(ErrorMessage)
+}
+
+impl From<CommandError> for LookupError {
+    fn from(err: CommandError) -> Self {
+        LookupError::Command(err)
+    }
+}
+
+impl From<ErrorMessage> for LookupError {
+    fn from(err: ErrorMessage) -> Self {
+        LookupError::Other(err)
+    }
+}
+
+/// Error of the command segment with given name, which keeps the severity and hint of the cause.
+#[derive(Debug)]
+struct SegmentError<'a> {
+    name: &'a str,
+    err: LookupError
+}
+
+impl<'a> fmt::Display for SegmentError<'a> {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        write!(fter, "{}", self.name)
+    }
+}
+
+impl<'a> Error for SegmentError<'a> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.err {
+            LookupError::Command(err) => Some(err),
+            LookupError::Other(err) => Some(err)
+        }
+    }
+}
+
+impl<'a> PluginError for SegmentError<'a> {
+    fn severity(&self) -> Severity {
+        match &self.err {
+            LookupError::Command(err) => err.severity(),
+            LookupError::Other(_) => Severity::Error
+        }
+    }
+
+    fn hint(&self) -> Option<&str> {
+        match &self.err {
+            LookupError::Command(err) => err.hint(),
+            LookupError::Other(_) => None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommandSegmentOutput {
+    text: String,
+    #[serde(default = "default_format")]
+    format: FormatLike
+}
+
+fn default_format() -> FormatLike { FormatLike::Text }
+
+/// Parses the stdout of a command, returns `None` if there is nothing to show.
+fn parse_output(stdout: &str) -> Result<Option<CommandSegmentOutput>, ErrorMessage> {
+    let stdout = stdout.trim();
+    let output =
+        if stdout.starts_with('{') {
+            serde_json::from_str(stdout)
+                .map_err(|err| ErrorMessage::new(format!("invalid json output: {}", err)))?
+        } else {
+            let text = stdout.lines().next().unwrap_or("").to_owned();
+            CommandSegmentOutput { text, format: default_format() }
+        };
+
+    if output.text.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(output))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::describe;
+
+    fn output(text: &str, format: FormatLike) -> Option<CommandSegmentOutput> {
+        Some(CommandSegmentOutput { text: text.to_owned(), format })
+    }
+
+    #[test]
+    fn plain_text_output() {
+        assert_eq!(parse_output("prod\n").unwrap(), output("prod", FormatLike::Text));
+        assert_eq!(parse_output("first\nsecond\n").unwrap(), output("first", FormatLike::Text));
+        assert_eq!(parse_output("  \n").unwrap(), None);
+    }
+
+    #[test]
+    fn json_output() {
+        assert_eq!(
+            parse_output(r#"{"text": "TICKET-12", "format": "hard_warning"}"#).unwrap(),
+            output("TICKET-12", FormatLike::HardWarning)
+        );
+        assert_eq!(parse_output(r#"{"text": "staging"}"#).unwrap(), output("staging", FormatLike::Text));
+        assert_eq!(parse_output(r#"{"text": ""}"#).unwrap(), None);
+    }
+
+    #[test]
+    fn timeouts_are_warnings() {
+        let options = CommandOptions { command: "sleep 1".to_owned(), when: None, timeout_ms: 10, cache_ttl_secs: 0 };
+        let segment = CommandSegment::new("slow", options);
+        let err = SegmentError { name: "slow", err: segment.lookup_segment().unwrap_err() };
+        assert_eq!(err.severity(), Severity::Warning);
+        assert_eq!(describe(&err), "slow: `sleep 1` timed out after 10ms (increase `timeout_ms` of the segment)");
+    }
+
+    #[test]
+    fn invalid_json_output() {
+        assert!(parse_output(r#"{"text": "a", "format": "blinking"}"#).is_err());
+        assert!(parse_output(r#"{"txt": "a"}"#).is_err());
+    }
+}
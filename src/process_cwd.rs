@@ -1,81 +1,778 @@
 const ERR_SCOPE: &str = "cwd";
 
 use std::{
-    path::{Path, PathBuf},
-    ffi::OsStr
+    cmp::{min, Reverse},
+    collections::BTreeMap,
+    iter,
+    str,
+    path::{Path, PathBuf, Component},
+    ffi::OsStr,
+    os::unix::ffi::OsStrExt
 };
 
 use crate::{
-    iface::{TerminalPlugin, FormatLike, CwdPathPlugin, WithNotAvailableVariant, ErrorMessage}
+    iface::{TerminalPlugin, FormatLike, CwdPathPlugin},
+    error::CwdError,
+    url
 };
 
-pub(crate) fn process_cwd<CWD, T>(terminal: &mut T)
+const ELLIPSIS: char = '…';
+
+/// How the path is abbreviated before it's displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Abbreviation {
+    /// Show the full path.
+    None,
+    /// Shorten all but the last `keep_last` components, e.g. `s/p/plugin_impl`.
+    Fish,
+    /// Only show the last `keep_last` components, e.g. `…/src/plugin_impl`.
+    Last,
+    /// Cut out the middle of the path so that it's at most `max_length` chars long.
+    Middle,
+    /// Like `Fish` but only shortens components (from the left) until the path fits.
+    Fit
+}
+
+/// Options of the `cwd` segment.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CwdOptions {
+    pub abbreviate: Abbreviation,
+    /// Defaults to 3 for `Last` and 1 for all other abbreviations.
+    pub keep_last: Option<usize>,
+    /// Defaults to the space available in a prompt line.
+    pub max_length: Option<usize>,
+    /// Shorten components to the shortest prefix not shared with a sibling directory,
+    /// instead of to their first char.
+    pub unique_prefix: bool,
+    /// Show paths inside of a repository relative to the repository root.
+    ///
+    /// Not used for repositories containing the home dir (e.g. for dotfiles), so that
+    /// paths in it are still shown relative to the home dir.
+    pub repo_root: bool,
+    /// Directories which are shown by a name instead of their path, e.g. `"/srv/acme" = "@acme"`.
+    ///
+    /// A leading `~` is replaced with the home directory. Aliases take precedence over
+    /// repository roots and if multiple aliases match the longest one is used. Their
+    /// order is not kept, so ties are broken by preferring the logical path.
+    pub aliases: BTreeMap<PathBuf, String>,
+    /// If the cwd was entered through a symlink, additionally show the physical path.
+    pub show_symlink_target: bool,
+    /// Shown after the path if the cwd is not writable, an empty string disables it.
+    pub read_only_symbol: String,
+    /// Make the path a `file://` hyperlink to the cwd (if the terminal supports it).
+    pub link: bool
+}
+
+impl Default for CwdOptions {
+    fn default() -> Self {
+        CwdOptions {
+            abbreviate: Abbreviation::None,
+            keep_last: None,
+            max_length: None,
+            unique_prefix: false,
+            repo_root: false,
+            aliases: BTreeMap::new(),
+            show_symlink_target: false,
+            read_only_symbol: "🔒".to_owned(),
+            link: true
+        }
+    }
+}
+
+pub fn process_cwd<CWD, T>(cwd: &CWD, options: &CwdOptions, terminal: &mut T)
     where CWD: CwdPathPlugin, T: TerminalPlugin
 {
 
     let base_path =
-        match CWD::get_current_path() {
+        match cwd.get_current_path() {
             Ok(path) => path,
             Err(err) => {
-                terminal.add_text_segment("????", FormatLike::Text);
-                terminal.add_error_segment(ERR_SCOPE, err.msg());
+                match cwd.get_deleted_path() {
+                    Some(path) => {
+                        let out = PathOutput { cwd, options, escapes: needs_escaping(path.as_os_str()) };
+                        out.output_cwd(terminal, &[&path]);
+                        terminal.extend_previous_segment("(deleted)", FormatLike::HardWarning);
+                    },
+                    None => {
+                        terminal.add_text_segment("????", FormatLike::Text);
+                        terminal.add_error(ERR_SCOPE, &err);
+                    }
+                }
                 return;
             }
         };
 
-    if let Ok(()) = try_output_prefix_stripped_path(terminal, &base_path, CWD::get_top_path()) {
-        return;
+    // prefixes (e.g. the home dir) might be given in the logical or the physical form
+    let physical_path = cwd.get_physical_path(&base_path).ok()
+        .filter(|physical_path| *physical_path != base_path);
+    let base_paths = iter::once(base_path.as_path())
+        .chain(physical_path.as_deref())
+        .collect::<Vec<_>>();
+
+    let escapes = base_paths.iter().any(|path| needs_escaping(path.as_os_str()));
+    let out = PathOutput { cwd, options, escapes };
+    out.output_cwd(terminal, &base_paths);
+
+    if options.link {
+        terminal.link_previous_segment(&url::file_url(&base_path));
+    }
+
+    if !options.read_only_symbol.is_empty() && !cwd.is_writable(&base_path) {
+        terminal.extend_previous_segment(&options.read_only_symbol, FormatLike::SoftWarning);
     }
 
-    if let Ok(()) = try_output_prefix_stripped_path(terminal, &base_path, CWD::get_home_path()) {
-        return;
+    if let (true, Some(physical_path)) = (options.show_symlink_target, physical_path) {
+        let home = cwd.get_home_path().ok();
+        let target =
+            match home.as_ref().and_then(|home| physical_path.strip_prefix(home).ok()) {
+                Some(rest) if is_empty_path(rest) => "~".to_owned(),
+                Some(rest) => format!("~/{}", escape(rest.as_os_str())),
+                None => escape(physical_path.as_os_str())
+            };
+        terminal.extend_previous_segment(&format!("→ {}", target), FormatLike::SymlinkTarget);
     }
+}
 
-    output_path(terminal, &base_path, FormatLike::PrimaryText);
+struct PathOutput<'a, CWD: 'a> {
+    cwd: &'a CWD,
+    options: &'a CwdOptions,
+    /// If the output contains escape sequences (see [`escape`]) which should be highlighted.
+    escapes: bool
 }
 
-fn try_output_prefix_stripped_path(
-    terminal: &mut impl TerminalPlugin,
-    base_path: &Path,
-    prefix: Result<PathBuf, WithNotAvailableVariant<ErrorMessage>>
-) -> Result<(), ()> {
-     match prefix {
-        Ok(prefix) => {
-            if let Ok(path) = base_path.strip_prefix(prefix) {
-                if is_empty_path(path) {
-                    let last = base_path.file_name().unwrap_or(OsStr::new(""));
-                    output_path(terminal, Path::new(last), FormatLike::Hidden);
-                } else {
-                    output_path(terminal, path, FormatLike::PrimaryText);
+impl<'a, CWD> PathOutput<'a, CWD>
+    where CWD: CwdPathPlugin
+{
+    /// Outputs the cwd, `base_paths` contains its logical and physical form.
+    fn output_cwd(&self, terminal: &mut impl TerminalPlugin, base_paths: &[&Path]) {
+        let base_path = base_paths[0];
+
+        if let Ok(()) = self.try_output_alias_relative_path(terminal, base_paths) {
+            return;
+        }
+
+        if self.options.repo_root {
+            if let Ok(()) = self.try_output_repo_relative_path(terminal, base_path) {
+                return;
+            }
+        }
+
+        if let Ok(()) = self.try_output_prefix_stripped_path(terminal, base_paths, self.cwd.get_top_path()) {
+            return;
+        }
+
+        if let Ok(()) = self.try_output_prefix_stripped_path(terminal, base_paths, self.cwd.get_home_path()) {
+            return;
+        }
+
+        self.output_path(terminal, Path::new(""), base_path, FormatLike::PrimaryText);
+    }
+
+    fn try_output_repo_relative_path(
+        &self,
+        terminal: &mut impl TerminalPlugin,
+        base_path: &Path
+    ) -> Result<(), ()> {
+        let root =
+            match self.cwd.get_vcs_root(base_path) {
+                Ok(root) => root,
+                Err(err) => {
+                    output_non_not_available_errors(terminal, &err);
+                    return Err(());
                 }
-                return Ok(());
+            };
+
+        if let Ok(home) = self.cwd.get_home_path() {
+            if home.starts_with(&root) {
+                return Err(());
             }
+        }
+
+        let path = base_path.strip_prefix(&root).map_err(|_| ())?;
+        let name = escape(root.file_name().unwrap_or(OsStr::new("")));
+        self.output_named_root_relative_path(terminal, (&name, FormatLike::RepoName), &root, path);
+        Ok(())
+    }
+
+    fn try_output_alias_relative_path(
+        &self,
+        terminal: &mut impl TerminalPlugin,
+        base_paths: &[&Path]
+    ) -> Result<(), ()> {
+        if self.options.aliases.is_empty() {
+            return Err(());
+        }
+
+        let home = self.cwd.get_home_path().ok();
+        let (_, base_path, root, alias) = self.options.aliases.iter()
+            .map(|(prefix, alias)| (expand_home(prefix, home.as_deref()), alias))
+            .flat_map(|(prefix, alias)| {
+                base_paths.iter().enumerate()
+                    .map(move |(idx, base_path)| (idx, *base_path, prefix.clone(), alias))
+            })
+            .filter(|(_, base_path, prefix, _)| base_path.starts_with(prefix))
+            // the longest prefix, the logical path (the first base path) for ties
+            .max_by_key(|(idx, _, prefix, _)| (prefix.components().count(), Reverse(*idx)))
+            .ok_or(())?;
+
+        let path = base_path.strip_prefix(&root).map_err(|_| ())?;
+        self.output_named_root_relative_path(terminal, (alias, FormatLike::PathAlias), &root, path);
+        Ok(())
+    }
+
+    /// Outputs `path` relative to `root`, with `root` being represented by a name.
+    fn output_named_root_relative_path(
+        &self,
+        terminal: &mut impl TerminalPlugin,
+        (name, name_fmt): (&str, FormatLike),
+        root: &Path,
+        path: &Path
+    ) {
+        let mut parts = self.split_escapes(name, name_fmt);
+        if is_empty_path(path) {
+            terminal.add_text_segment_parts(&parts);
+        } else {
+            let columns = terminal.column_count().saturating_sub(name.chars().count() + 1);
+            let text = self.format_path(columns, root, path);
+            parts.push(("/", FormatLike::Hidden));
+            parts.extend(self.split_escapes(&text, FormatLike::PrimaryText));
+            terminal.add_text_segment_parts(&parts);
+        }
+    }
+
+    fn try_output_prefix_stripped_path(
+        &self,
+        terminal: &mut impl TerminalPlugin,
+        base_paths: &[&Path],
+        prefix: Result<PathBuf, CwdError>
+    ) -> Result<(), ()> {
+         match prefix {
+            Ok(prefix) => {
+                for base_path in base_paths {
+                    if let Ok(path) = base_path.strip_prefix(&prefix) {
+                        if is_empty_path(path) {
+                            let last = escape(base_path.file_name().unwrap_or(OsStr::new("")));
+                            terminal.add_text_segment_parts(&self.split_escapes(&last, FormatLike::Hidden));
+                        } else {
+                            self.output_path(terminal, &prefix, path, FormatLike::PrimaryText);
+                        }
+                        return Ok(());
+                    }
+                }
+            },
+            Err(err) => {
+                output_non_not_available_errors(terminal, &err);
+            }
+        }
+        Err(())
+    }
+
+    /// Outputs the (abbreviated) `path`, which is relative to `base`.
+    fn output_path(&self, out: &mut impl TerminalPlugin, base: &Path, path: &Path, fmt: FormatLike) {
+        let text = self.format_path(out.column_count(), base, path);
+        out.add_text_segment_parts(&self.split_escapes(&text, fmt));
+    }
+
+    /// Splits `text` into parts, so that escape sequences are highlighted.
+    fn split_escapes<'t>(&self, text: &'t str, fmt: FormatLike) -> Vec<(&'t str, FormatLike)> {
+        if self.escapes {
+            split_escapes(text, fmt)
+        } else {
+            vec![(text, fmt)]
+        }
+    }
+
+    /// Formats the (abbreviated and escaped) `path`, which is relative to `base`.
+    fn format_path(&self, columns: usize, base: &Path, path: &Path) -> String {
+        if self.options.abbreviate == Abbreviation::None {
+            return escape(path.as_os_str());
+        }
+
+        let mut root = String::new();
+        let mut parts = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::RootDir => root.push('/'),
+                other => parts.push(escape(other.as_os_str()))
+            }
+        }
+
+        // -3 as in the `╠` or similar line start and TEXT_START, TEXT_END
+        let limit = self.options.max_length
+            .unwrap_or_else(|| columns.saturating_sub(3));
+
+        abbreviate(&root, &parts, self.options, limit, |idx| {
+            let parent = base.join(path.iter().take(idx + root.len()).collect::<PathBuf>());
+            self.cwd.get_sub_dir_names(&parent)
+                .map(|names| names.iter().map(|name| escape(name)).collect())
+                .unwrap_or_default()
+        })
+    }
+}
+
+/// Abbreviates the path made up of the `root` and `parts`.
+///
+/// `siblings` is called with the index of a part to get the names of
+/// the directories which are in the same directory as that part.
+fn abbreviate(
+    root: &str,
+    parts: &[String],
+    options: &CwdOptions,
+    limit: usize,
+    mut siblings: impl FnMut(usize) -> Vec<String>
+) -> String {
+    let default_keep_last = if options.abbreviate == Abbreviation::Last { 3 } else { 1 };
+    let keep_last = options.keep_last.unwrap_or(default_keep_last);
+    let abbreviatable = parts.len().saturating_sub(keep_last);
+    let mut parts = parts.to_vec();
+
+    match options.abbreviate {
+        Abbreviation::None => join(root, &parts),
+        Abbreviation::Fish => {
+            for (idx, part) in parts.iter_mut().take(abbreviatable).enumerate() {
+                *part = shorten(part, options.unique_prefix, || siblings(idx));
+            }
+            join(root, &parts)
         },
-        Err(err) => {
-            output_non_not_available_errors(terminal, &err);
+        Abbreviation::Last => {
+            if abbreviatable > 0 {
+                format!("{}/{}", ELLIPSIS, parts[abbreviatable..].join("/"))
+            } else {
+                join(root, &parts)
+            }
+        },
+        Abbreviation::Middle => truncate_middle(join(root, &parts), limit),
+        Abbreviation::Fit => {
+            for idx in 0..abbreviatable {
+                if join(root, &parts).chars().count() <= limit {
+                    break;
+                }
+                parts[idx] = shorten(&parts[idx], options.unique_prefix, || siblings(idx));
+            }
+            truncate_middle(join(root, &parts), limit)
         }
     }
-    Err(())
+}
+
+fn join(root: &str, parts: &[String]) -> String {
+    format!("{}{}", root, parts.join("/"))
+}
+
+/// Shortens a path component to its first char (ignoring leading dots) or,
+/// if `unique` is set, the shortest prefix which no sibling starts with.
+fn shorten(name: &str, unique: bool, siblings: impl FnOnce() -> Vec<String>) -> String {
+    let units = units(name);
+    let dots = units.iter().take_while(|unit| **unit == ".").count();
+    let min_len = min(dots + 1, units.len());
+
+    if !unique {
+        return units[..min_len].concat();
+    }
+
+    let siblings = siblings();
+    for len in min_len..units.len() {
+        let prefix = units[..len].concat();
+        let is_unique = siblings.iter()
+            .all(|sibling| sibling == name || !sibling.starts_with(&prefix));
+        if is_unique {
+            return prefix;
+        }
+    }
+    name.to_owned()
+}
+
+fn truncate_middle(text: String, limit: usize) -> String {
+    let len = text.chars().count();
+    if len <= limit {
+        return text;
+    }
+
+    let keep = limit.saturating_sub(1);
+    let head = keep / 2;
+    let tail = keep - head;
+
+    let units = units(&text);
+    let mut out = take_units(units.iter(), head).concat();
+    out.push(ELLIPSIS);
+    let mut tail_units = take_units(units.iter().rev(), tail);
+    tail_units.reverse();
+    out.push_str(&tail_units.concat());
+    out
+}
+
+/// Takes units from the iterator as long as they fit into `limit` chars.
+fn take_units<'t>(units: impl Iterator<Item=&'t &'t str>, limit: usize) -> Vec<&'t str> {
+    let mut len = 0;
+    units
+        .take_while(|unit| {
+            len += unit.chars().count();
+            len <= limit
+        })
+        .cloned()
+        .collect()
+}
+
+fn expand_home(path: &Path, home: Option<&Path>) -> PathBuf {
+    match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf()
+    }
 }
 
 fn is_empty_path(path: &Path) -> bool {
     path.iter().next().is_none()
 }
 
-fn output_path(out: &mut impl TerminalPlugin, path: &Path, fmt: FormatLike) {
-    if let Some(str_form) = path.to_str() {
-        out.add_text_segment(str_form, fmt);
+/// Returns true if [`escape`] would change `text`.
+fn needs_escaping(text: &OsStr) -> bool {
+    match text.to_str() {
+        Some(text) => text.chars().any(|ch| ch.is_ascii_control() || ch == '\\'),
+        None => true
+    }
+}
+
+/// Converts `text` to a string, escaping bytes which are not valid UTF-8 and
+/// ASCII control chars (which could mess up the terminal) as `\xHH`, and `\` as `\\`
+/// so that escapes can be told apart from names which look like one.
+fn escape(text: &OsStr) -> String {
+    let mut out = String::new();
+    let mut bytes = text.as_bytes();
+    loop {
+        match str::from_utf8(bytes) {
+            Ok(valid) => {
+                push_escaping_control_chars(&mut out, valid);
+                return out;
+            },
+            Err(err) => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                push_escaping_control_chars(&mut out, str::from_utf8(valid).unwrap_or_default());
+                let invalid_len = err.error_len().unwrap_or(rest.len());
+                for byte in &rest[..invalid_len] {
+                    out.push_str(&format!("\\x{:02X}", byte));
+                }
+                bytes = &rest[invalid_len..];
+            }
+        }
+    }
+}
+
+fn push_escaping_control_chars(out: &mut String, text: &str) {
+    for ch in text.chars() {
+        if ch.is_ascii_control() {
+            out.push_str(&format!("\\x{:02X}", ch as u8));
+        } else if ch == '\\' {
+            out.push_str("\\\\");
+        } else {
+            out.push(ch);
+        }
+    }
+}
+
+/// Returns the length of the escape sequence created by [`escape`] `text` starts with, if any.
+fn escape_len(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    if bytes.starts_with(b"\\\\") {
+        Some(2)
+    } else if bytes.len() >= 4 && bytes.starts_with(b"\\x")
+        && bytes[2].is_ascii_hexdigit() && bytes[3].is_ascii_hexdigit()
+    {
+        Some(4)
     } else {
-        out.add_text_segment(&format!("{}", path.display()), fmt);
+        None
     }
 }
 
-fn output_non_not_available_errors(
-    out: &mut impl TerminalPlugin,
-    err: &WithNotAvailableVariant<ErrorMessage>
-) {
+/// Splits escaped `text` into its chars and escape sequences, which must not be cut in half.
+fn units(text: &str) -> Vec<&str> {
+    let mut units = Vec::new();
+    let mut idx = 0;
+    while idx < text.len() {
+        let len = escape_len(&text[idx..])
+            .unwrap_or_else(|| text[idx..].chars().next().map(char::len_utf8).unwrap_or(1));
+        units.push(&text[idx..idx + len]);
+        idx += len;
+    }
+    units
+}
+
+/// Splits `text` into parts with `fmt` and the escape sequences
+/// created by [`escape`] with [`FormatLike::EscapedBytes`].
+fn split_escapes(text: &str, fmt: FormatLike) -> Vec<(&str, FormatLike)> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut idx = 0;
+    while idx < text.len() {
+        if escape_len(&text[idx..]).is_some() {
+            if start < idx {
+                parts.push((&text[start..idx], fmt));
+            }
+            let mut end = idx;
+            while let Some(len) = escape_len(&text[end..]) {
+                end += len;
+            }
+            parts.push((&text[idx..end], FormatLike::EscapedBytes));
+            start = end;
+            idx = end;
+        } else {
+            idx += text[idx..].chars().next().map(char::len_utf8).unwrap_or(1);
+        }
+    }
+    if start < text.len() || parts.is_empty() {
+        parts.push((&text[start..], fmt));
+    }
+    parts
+}
+
+fn output_non_not_available_errors(out: &mut impl TerminalPlugin, err: &CwdError) {
     match err {
-        WithNotAvailableVariant::Err(err) => out.add_error_segment(ERR_SCOPE, err.msg()),
-        WithNotAvailableVariant::NotAvailable => {}
+        CwdError::NotAvailable => {},
+        err => out.add_error(ERR_SCOPE, err)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{RecordingTerminal, FakeCwd};
+
+    fn render(cwd: FakeCwd, options: CwdOptions) -> RecordingTerminal {
+        let mut terminal = RecordingTerminal::new(80);
+        process_cwd(&cwd, &options, &mut terminal);
+        terminal
+    }
+
+    #[test]
+    fn repo_relative_path() {
+        let repo_root = || CwdOptions { repo_root: true, ..Default::default() };
+        let cwd = FakeCwd { current: "/home/u/src/prompty/src/plugin_impl".into(), vcs_root: Some("/home/u/src/prompty"), home: Some("/home/u"), ..Default::default() };
+        let terminal = render(cwd, repo_root());
+        assert_eq!(terminal.segments, vec![vec![
+            ("prompty".to_owned(), FormatLike::RepoName),
+            ("/".to_owned(), FormatLike::Hidden),
+            ("src/plugin_impl".to_owned(), FormatLike::PrimaryText)
+        ]]);
+
+        let cwd = FakeCwd { current: "/home/u/src/prompty".into(), vcs_root: Some("/home/u/src/prompty"), home: Some("/home/u"), ..Default::default() };
+        let terminal = render(cwd, repo_root());
+        assert_eq!(terminal.segments, vec![vec![("prompty".to_owned(), FormatLike::RepoName)]]);
+
+        // e.g. a dotfiles repository
+        let cwd = FakeCwd { current: "/home/u/.config".into(), vcs_root: Some("/home/u"), home: Some("/home/u"), ..Default::default() };
+        let terminal = render(cwd, repo_root());
+        assert_eq!(terminal.texts(), vec![".config"]);
+    }
+
+    #[test]
+    fn longest_alias_is_used() {
+        let cwd = FakeCwd { current: "/srv/projects/acme/web/src".into(), vcs_root: Some("/srv/projects/acme/web"), home: Some("/home/u"), ..Default::default() };
+        let mut options = CwdOptions::default();
+        options.aliases.insert(PathBuf::from("/srv/projects"), "@projects".to_owned());
+        options.aliases.insert(PathBuf::from("/srv/projects/acme"), "@acme".to_owned());
+        let terminal = render(cwd, options);
+        assert_eq!(terminal.segments, vec![vec![
+            ("@acme".to_owned(), FormatLike::PathAlias),
+            ("/".to_owned(), FormatLike::Hidden),
+            ("web/src".to_owned(), FormatLike::PrimaryText)
+        ]]);
+    }
+
+    #[test]
+    fn aliases_of_the_logical_path_win_ties() {
+        let cwd = FakeCwd { current: "/a/link/sub".into(), physical: Some("/w/real/sub"), ..Default::default() };
+        let mut options = CwdOptions::default();
+        options.aliases.insert(PathBuf::from("/w/real"), "@real".to_owned());
+        options.aliases.insert(PathBuf::from("/a/link"), "@link".to_owned());
+        let terminal = render(cwd, options);
+        assert_eq!(terminal.texts(), vec!["@link/sub"]);
+    }
+
+    #[test]
+    fn aliases_expand_home() {
+        let cwd = FakeCwd { current: "/home/u/work".into(), home: Some("/home/u"), ..Default::default() };
+        let mut options = CwdOptions::default();
+        options.aliases.insert(PathBuf::from("~/work"), "@work".to_owned());
+        let terminal = render(cwd, options);
+        assert_eq!(terminal.segments, vec![vec![("@work".to_owned(), FormatLike::PathAlias)]]);
+    }
+
+    #[test]
+    fn repo_root_is_disabled_by_default() {
+        let cwd = FakeCwd { current: "/home/u/src/prompty/src".into(), vcs_root: Some("/home/u/src/prompty"), home: Some("/home/u"), ..Default::default() };
+        let terminal = render(cwd, CwdOptions::default());
+        assert_eq!(terminal.texts(), vec!["src/prompty/src"]);
+    }
+
+    #[test]
+    fn prefixes_match_logical_and_physical_path() {
+        let cwd = FakeCwd { current: "/home/u/link/sub".into(), home: Some("/home/u"), physical: Some("/data/real/sub"), ..Default::default() };
+        let terminal = render(cwd, CwdOptions::default());
+        assert_eq!(terminal.texts(), vec!["link/sub"]);
+
+        let cwd = FakeCwd { current: "/link/sub".into(), home: Some("/home/u"), physical: Some("/home/u/real/sub"), ..Default::default() };
+        let terminal = render(cwd, CwdOptions::default());
+        assert_eq!(terminal.texts(), vec!["real/sub"]);
+    }
+
+    #[test]
+    fn symlink_target() {
+        let cwd = FakeCwd { current: "/home/u/link/sub".into(), home: Some("/home/u"), physical: Some("/home/u/real/sub"), ..Default::default() };
+        let terminal = render(cwd, CwdOptions { show_symlink_target: true, ..Default::default() });
+        assert_eq!(terminal.segments, vec![vec![
+            ("link/sub".to_owned(), FormatLike::PrimaryText),
+            ("→ ~/real/sub".to_owned(), FormatLike::SymlinkTarget)
+        ]]);
+
+        let cwd = FakeCwd { current: "/home/u/sub".into(), home: Some("/home/u"), ..Default::default() };
+        let terminal = render(cwd, CwdOptions { show_symlink_target: true, ..Default::default() });
+        assert_eq!(terminal.texts(), vec!["sub"]);
+    }
+
+    #[test]
+    fn cwd_link() {
+        let cwd = FakeCwd { current: "/home/u/my dir".into(), home: Some("/home/u"), ..Default::default() };
+        let terminal = render(cwd, CwdOptions::default());
+        assert_eq!(terminal.links.len(), 1);
+        assert!(terminal.links[0].starts_with("file://"));
+        assert!(terminal.links[0].ends_with("/home/u/my%20dir"));
+
+        let cwd = FakeCwd { current: "/home/u/my dir".into(), home: Some("/home/u"), ..Default::default() };
+        let terminal = render(cwd, CwdOptions { link: false, ..Default::default() });
+        assert!(terminal.links.is_empty());
+    }
+
+    #[test]
+    fn read_only_indicator() {
+        let cwd = FakeCwd { current: "/read-only/dir".into(), ..Default::default() };
+        let terminal = render(cwd, CwdOptions::default());
+        assert_eq!(terminal.segments, vec![vec![
+            ("/read-only/dir".to_owned(), FormatLike::PrimaryText),
+            ("🔒".to_owned(), FormatLike::SoftWarning)
+        ]]);
+
+        let cwd = FakeCwd { current: "/read-only/dir".into(), ..Default::default() };
+        let terminal = render(cwd, CwdOptions { read_only_symbol: String::new(), ..Default::default() });
+        assert_eq!(terminal.texts(), vec!["/read-only/dir"]);
+    }
+
+    #[test]
+    fn deleted_cwd() {
+        let cwd = FakeCwd { current: "/home/u/gone".into(), home: Some("/home/u"), deleted: true, ..Default::default() };
+        let terminal = render(cwd, CwdOptions::default());
+        assert_eq!(terminal.segments, vec![vec![
+            ("gone".to_owned(), FormatLike::PrimaryText),
+            ("(deleted)".to_owned(), FormatLike::HardWarning)
+        ]]);
+        assert!(terminal.errors.is_empty());
+    }
+
+    #[test]
+    fn escaping_invalid_utf8_and_control_chars() {
+        use std::os::unix::ffi::OsStrExt;
+
+        assert_eq!(escape(OsStr::from_bytes(b"abc/d\xFFe\xC3")), "abc/d\\xFFe\\xC3");
+        assert_eq!(escape(OsStr::new("tab\there")), "tab\\x09here");
+        assert_eq!(escape(OsStr::new("äöü")), "äöü");
+        assert!(needs_escaping(OsStr::from_bytes(b"\xFF")));
+        assert!(!needs_escaping(OsStr::new("äöü")));
+        assert_eq!(escape(OsStr::new("a\\x41")), "a\\\\x41");
+        assert!(needs_escaping(OsStr::new("a\\b")));
+
+        assert_eq!(split_escapes("a\\xFF\\x01b", FormatLike::Text), vec![
+            ("a", FormatLike::Text),
+            ("\\xFF\\x01", FormatLike::EscapedBytes),
+            ("b", FormatLike::Text)
+        ]);
+        assert_eq!(split_escapes("\\xFF", FormatLike::Text), vec![("\\xFF", FormatLike::EscapedBytes)]);
+        assert_eq!(split_escapes("", FormatLike::Text), vec![("", FormatLike::Text)]);
+        assert_eq!(split_escapes("a\\\\x41", FormatLike::Text), vec![
+            ("a", FormatLike::Text),
+            ("\\\\", FormatLike::EscapedBytes),
+            ("x41", FormatLike::Text)
+        ]);
+    }
+
+    #[test]
+    fn non_utf8_path() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let cwd = FakeCwd {
+            current: OsStr::from_bytes(b"/home/u/d\xE4ta").into(),
+            home: Some("/home/u"),
+            ..Default::default()
+        };
+        let terminal = render(cwd, CwdOptions::default());
+        assert_eq!(terminal.segments, vec![vec![
+            ("d".to_owned(), FormatLike::PrimaryText),
+            ("\\xE4".to_owned(), FormatLike::EscapedBytes),
+            ("ta".to_owned(), FormatLike::PrimaryText)
+        ]]);
+    }
+
+    fn parts(path: &str) -> Vec<String> {
+        path.split('/').map(|part| part.to_owned()).collect()
+    }
+
+    fn options(abbreviate: Abbreviation) -> CwdOptions {
+        CwdOptions { abbreviate, ..Default::default() }
+    }
+
+    fn no_siblings(_: usize) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[test]
+    fn fish_style() {
+        let opts = options(Abbreviation::Fish);
+        assert_eq!(abbreviate("", &parts("src/prompty/plugin_impl"), &opts, 80, no_siblings), "s/p/plugin_impl");
+        assert_eq!(abbreviate("/", &parts("usr/.local/lib"), &opts, 80, no_siblings), "/u/.l/lib");
+
+        let opts = CwdOptions { keep_last: Some(2), ..opts };
+        assert_eq!(abbreviate("", &parts("src/prompty/plugin_impl"), &opts, 80, no_siblings), "s/prompty/plugin_impl");
+    }
+
+    #[test]
+    fn fish_style_with_unique_prefix() {
+        let opts = CwdOptions { unique_prefix: true, ..options(Abbreviation::Fish) };
+        let siblings = |idx| match idx {
+            0 => vec!["src".to_owned(), "scripts".to_owned(), "target".to_owned()],
+            1 => vec!["prompty".to_owned(), "prompt".to_owned()],
+            _ => vec![]
+        };
+        assert_eq!(abbreviate("", &parts("src/prompt/x"), &opts, 80, siblings), "sr/prompt/x");
+    }
+
+    #[test]
+    fn last_n_components() {
+        let opts = CwdOptions { keep_last: Some(2), ..options(Abbreviation::Last) };
+        assert_eq!(abbreviate("/", &parts("a/b/c/d"), &opts, 80, no_siblings), "…/c/d");
+        assert_eq!(abbreviate("/", &parts("c/d"), &opts, 80, no_siblings), "/c/d");
+    }
+
+    #[test]
+    fn middle_truncation() {
+        let opts = options(Abbreviation::Middle);
+        assert_eq!(abbreviate("/", &parts("abcd/efgh/ijkl"), &opts, 9, no_siblings), "/abc…ijkl");
+        assert_eq!(abbreviate("", &parts("abcd"), &opts, 9, no_siblings), "abcd");
+        // escapes are kept whole, so the path is a bit shorter than the limit
+        assert_eq!(abbreviate("", &parts("a\\xFFbcdefgh\\xFF"), &opts, 9, no_siblings), "a…\\xFF");
+    }
+
+    #[test]
+    fn shortening_keeps_escapes_whole() {
+        let opts = options(Abbreviation::Fish);
+        assert_eq!(abbreviate("", &parts("\\xFFbc/\\\\d/e"), &opts, 80, no_siblings), "\\xFF/\\\\/e");
+    }
+
+    #[test]
+    fn shorten_until_fits() {
+        let opts = options(Abbreviation::Fit);
+        let path = parts("alpha/beta/gamma/delta");
+        assert_eq!(abbreviate("", &path, &opts, 80, no_siblings), "alpha/beta/gamma/delta");
+        assert_eq!(abbreviate("", &path, &opts, 18, no_siblings), "a/beta/gamma/delta");
+        assert_eq!(abbreviate("", &path, &opts, 11, no_siblings), "a/b/g/delta");
+        assert_eq!(abbreviate("", &path, &opts, 7, no_siblings), "a/b…lta");
     }
 }
@@ -0,0 +1,13 @@
+use crate::iface::{TerminalPlugin, FormatLike};
+
+pub fn process_exit_status<T>(terminal: &mut T, status: i32, show_success: bool)
+    where T: TerminalPlugin
+{
+    if status == 0 {
+        if show_success {
+            terminal.add_text_segment("0", FormatLike::ExplicitOk);
+        }
+    } else {
+        terminal.add_text_segment(&status.to_string(), FormatLike::HardWarning);
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    env,
+    error::Error,
+    fmt,
+    fs,
+    path::PathBuf,
+    rc::Rc
+};
+
+use rhai::{Engine, AST, Dynamic, Map, Array, EvalAltResult};
+use serde::{Deserialize, de::IntoDeserializer};
+
+use crate::{
+    iface::{TerminalPlugin, CwdPathPlugin, GitPlugin, GitInfo, FormatLike, ErrorMessage},
+    error::{self, PluginError, GitError},
+    segment::Segment
+};
+
+const ERR_SCOPE: &str = "script";
+
+fn default_max_operations() -> u64 { 100_000 }
+
+/// Options of a segment of type `script`.
+///
+/// Exactly one of `script` and `file` has to be given.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptOptions {
+    /// The script source.
+    #[serde(default)]
+    script: Option<String>,
+    /// Path to a file containing the script source.
+    #[serde(default)]
+    file: Option<PathBuf>,
+    /// Limits the number of operations a script can run, to not hang the prompt.
+    #[serde(default = "default_max_operations")]
+    max_operations: u64
+}
+
+/// A segment defined by a [Rhai](https://rhai.rs) script.
+///
+/// The script can return `()` (no segment), a string, a `#{text: "..", format: ".."}`
+/// map (with format being optional) or an array of the former, and has access to:
+///
+/// - `env(name)`: the environment variable or `()`
+/// - `cwd()`: the current working directory (the logical one, like `$PWD`)
+/// - `git()`: `#{branch, has_untracked_files, has_unstaged_files, has_staged_files}`
+///   or `()` if not in a git repository
+/// - `exists(path)`, `is_file(path)`, `is_dir(path)`, `read_file(path)`
+///   and `list_dir(path)` for read only file system access
+pub struct ScriptSegment {
+    name: String,
+    engine: Engine,
+    ast: AST
+}
+
+impl ScriptSegment {
+    pub fn new<PATH, GIT>(name: &str, options: ScriptOptions, cwd: Rc<PATH>, git: Rc<GIT>)
+        -> Result<Self, ErrorMessage>
+        where PATH: CwdPathPlugin + 'static, GIT: GitPlugin + 'static
+    {
+        let source =
+            match (options.script, options.file) {
+                (Some(script), None) => script,
+                (None, Some(file)) => fs::read_to_string(&file)
+                    .map_err(|err| ErrorMessage::new(format!("can not read {}: {}", file.display(), err)))?,
+                _ => return Err(ErrorMessage::new("exactly one of `script` and `file` is required"))
+            };
+
+        let engine = create_engine(options.max_operations, cwd, git);
+        let ast = engine.compile(&source)
+            .map_err(|err| ErrorMessage::new(format!("invalid script: {}", err)))?;
+
+        Ok(ScriptSegment { name: name.to_owned(), engine, ast })
+    }
+
+    fn run(&self) -> Result<Vec<(String, FormatLike)>, RunError> {
+        let value = self.engine.eval_ast::<Dynamic>(&self.ast)
+            .map_err(RunError::Eval)?;
+        let mut segments = Vec::new();
+        collect_segments(value, &mut segments).map_err(RunError::InvalidResult)?;
+        Ok(segments)
+    }
+}
+
+impl<T> Segment<T> for ScriptSegment
+    where T: TerminalPlugin
+{
+    fn render(&self, terminal: &mut T) {
+        match self.run() {
+            Ok(segments) => {
+                for (text, fmt) in segments {
+                    terminal.add_text_segment(&text, fmt);
+                }
+            },
+            Err(err) => terminal.add_error(ERR_SCOPE, &SegmentError { name: &self.name, err })
+        }
+    }
+}
+
+/// Why a script didn't produce segments.
+#[derive(Debug)]
+enum RunError {
+    /// The script failed, e.g. because it exceeded `max_operations`.
+    Eval(Box<EvalAltResult>),
+    /// The script returned something which is not a segment.
+    InvalidResult(ErrorMessage)
+}
+
+/// Error of the script segment with given name.
+#[derive(Debug)]
+struct SegmentError<'a> {
+    name: &'a str,
+    err: RunError
+}
+
+impl<'a> fmt::Display for SegmentError<'a> {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        write!(fter, "{}", self.name)
+    }
+}
+
+impl<'a> Error for SegmentError<'a> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.err {
+            RunError::Eval(err) => Some(&**err),
+            RunError::InvalidResult(err) => Some(err)
+        }
+    }
+}
+
+impl<'a> PluginError for SegmentError<'a> {
+    fn hint(&self) -> Option<&str> {
+        match &self.err {
+            RunError::Eval(err) if matches!(**err, EvalAltResult::ErrorTooManyOperations(_)) =>
+                Some("increase `max_operations` in the segment options"),
+            _ => None
+        }
+    }
+}
+
+fn create_engine<PATH, GIT>(max_operations: u64, cwd: Rc<PATH>, git: Rc<GIT>) -> Engine
+    where PATH: CwdPathPlugin + 'static, GIT: GitPlugin + 'static
+{
+    let mut engine = Engine::new();
+    engine.set_max_operations(max_operations);
+
+    engine.register_fn("env", |name: &str| -> Dynamic {
+        env::var(name).map(Dynamic::from).unwrap_or(Dynamic::UNIT)
+    });
+    engine.register_fn("cwd", move || -> Result<String, Box<EvalAltResult>> {
+        let cwd = cwd.get_current_path().map_err(|err| error::describe(&err))?;
+        Ok(cwd.to_string_lossy().into_owned())
+    });
+    engine.register_fn("git", move || -> Result<Dynamic, Box<EvalAltResult>> {
+        match git.lookup_status() {
+            Ok(info) => Ok(git_info_to_map(info).into()),
+            Err(GitError::NotAvailable) => Ok(Dynamic::UNIT),
+            Err(err) => Err(format!("git: {}", error::describe(&err)).into())
+        }
+    });
+
+    engine.register_fn("exists", |path: &str| fs::metadata(path).is_ok());
+    engine.register_fn("is_file", |path: &str| fs::metadata(path).map(|m| m.is_file()).unwrap_or(false));
+    engine.register_fn("is_dir", |path: &str| fs::metadata(path).map(|m| m.is_dir()).unwrap_or(false));
+    engine.register_fn("read_file", |path: &str| -> Result<String, Box<EvalAltResult>> {
+        fs::read_to_string(path)
+            .map_err(|err| format!("can not read {}: {}", path, err).into())
+    });
+    engine.register_fn("list_dir", |path: &str| -> Result<Array, Box<EvalAltResult>> {
+        let entries = fs::read_dir(path)
+            .map_err(|err| format!("can not list {}: {}", path, err))?;
+        let mut names = Array::new();
+        for entry in entries {
+            let entry = entry.map_err(|err| format!("can not list {}: {}", path, err))?;
+            names.push(entry.file_name().to_string_lossy().into_owned().into());
+        }
+        Ok(names)
+    });
+
+    engine
+}
+
+fn git_info_to_map(info: GitInfo) -> Map {
+    let GitInfo { branch, has_untracked_files, has_unstaged_files, has_staged_files } = info;
+    let mut map = Map::new();
+    map.insert("branch".into(), branch.into());
+    map.insert("has_untracked_files".into(), has_untracked_files.into());
+    map.insert("has_unstaged_files".into(), has_unstaged_files.into());
+    map.insert("has_staged_files".into(), has_staged_files.into());
+    map
+}
+
+fn collect_segments(value: Dynamic, out: &mut Vec<(String, FormatLike)>) -> Result<(), ErrorMessage> {
+    if value.is_unit() {
+        return Ok(());
+    }
+
+    let type_name = value.type_name();
+    if value.is_string() {
+        let text = value.into_string().unwrap_or_default();
+        push_non_empty(out, text, FormatLike::Text);
+    } else if value.is_array() {
+        for item in value.cast::<Array>() {
+            collect_segments(item, out)?;
+        }
+    } else if value.is_map() {
+        let mut map = value.cast::<Map>();
+        let text = map.remove("text")
+            .and_then(|text| text.into_string().ok())
+            .ok_or_else(|| ErrorMessage::new("returned map needs a string `text` field"))?;
+        let fmt =
+            match map.remove("format") {
+                Some(fmt) => {
+                    let fmt = fmt.into_string()
+                        .map_err(|_| ErrorMessage::new("`format` field needs to be a string"))?;
+                    parse_format(&fmt)?
+                },
+                None => FormatLike::Text
+            };
+        push_non_empty(out, text, fmt);
+    } else {
+        let msg = format!("script must return (), a string, a map or an array, got: {}", type_name);
+        return Err(ErrorMessage::new(msg));
+    }
+    Ok(())
+}
+
+fn push_non_empty(out: &mut Vec<(String, FormatLike)>, text: String, fmt: FormatLike) {
+    if !text.is_empty() {
+        out.push((text, fmt));
+    }
+}
+
+fn parse_format(name: &str) -> Result<FormatLike, ErrorMessage> {
+    let deserializer: serde::de::value::StrDeserializer<serde::de::value::Error> = name.into_deserializer();
+    FormatLike::deserialize(deserializer)
+        .map_err(|err| ErrorMessage::new(format!("invalid format: {}", err)))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{FakeCwd, FakeGit, RecordingTerminal};
+
+    fn segment(script: &str) -> ScriptSegment {
+        let options = ScriptOptions {
+            script: Some(script.to_owned()),
+            file: None,
+            max_operations: default_max_operations()
+        };
+        let cwd = FakeCwd { current: PathBuf::from("/home/u/src"), ..Default::default() };
+        let git = FakeGit { branch: "main", has_untracked_files: true, ..Default::default() };
+        ScriptSegment::new("test", options, Rc::new(cwd), Rc::new(git)).unwrap()
+    }
+
+    fn run(script: &str) -> Result<Vec<(String, FormatLike)>, RunError> {
+        segment(script).run()
+    }
+
+    fn text(text: &str, fmt: FormatLike) -> (String, FormatLike) {
+        (text.to_owned(), fmt)
+    }
+
+    #[test]
+    fn returning_segments() {
+        assert_eq!(run("()").unwrap(), vec![]);
+        assert_eq!(run(r#""hy""#).unwrap(), vec![text("hy", FormatLike::Text)]);
+        assert_eq!(
+            run(r#"#{ text: "hy", format: "soft_warning" }"#).unwrap(),
+            vec![text("hy", FormatLike::SoftWarning)]
+        );
+        assert_eq!(
+            run(r#"["a", (), #{ text: "b", format: "hidden" }]"#).unwrap(),
+            vec![text("a", FormatLike::Text), text("b", FormatLike::Hidden)]
+        );
+    }
+
+    #[test]
+    fn accessing_git_info() {
+        let script = r#"
+            let info = git();
+            if info.has_untracked_files { info.branch + "?" } else { info.branch }
+        "#;
+        assert_eq!(run(script).unwrap(), vec![text("main?", FormatLike::Text)]);
+    }
+
+    #[test]
+    fn accessing_the_logical_cwd() {
+        assert_eq!(run("cwd()").unwrap(), vec![text("/home/u/src", FormatLike::Text)]);
+    }
+
+    #[test]
+    fn invalid_scripts_and_results() {
+        let options = ScriptOptions { script: Some("let x = ".to_owned()), file: None, max_operations: 1 };
+        assert!(ScriptSegment::new("test", options, Rc::new(FakeCwd::default()), Rc::new(FakeGit::default())).is_err());
+        assert!(run("42").is_err());
+        assert!(run(r#"#{ text: "a", format: "sparkling" }"#).is_err());
+        assert!(run("loop {}").is_err());
+    }
+
+    #[test]
+    fn errors_name_the_segment_and_hint_at_the_limit() {
+        let mut terminal = RecordingTerminal::new(80);
+        segment("loop {}").render(&mut terminal);
+        assert_eq!(terminal.errors.len(), 1);
+        let (scope, msg) = &terminal.errors[0];
+        assert_eq!(*scope, ERR_SCOPE);
+        assert!(msg.starts_with("test: Too many operations"), "{}", msg);
+        assert!(msg.ends_with("(increase `max_operations` in the segment options)"), "{}", msg);
+    }
+}
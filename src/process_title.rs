@@ -0,0 +1,99 @@
+use std::env;
+
+use crate::{
+    iface::{TerminalPlugin, CwdPathPlugin, GitPlugin},
+    error::GitError,
+    plugin_impl::hostname
+};
+
+const ERR_SCOPE: &str = "title";
+
+fn default_template() -> String { "{user}@{host}: {cwd_short}".to_owned() }
+
+/// Options of the `title` segment.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TitleOptions {
+    /// Template for the title, supporting `{user}`, `{host}`, `{cwd}` (with `~` for the
+    /// home dir), `{cwd_short}` (the last path component) and `{git_branch}`.
+    pub template: String
+}
+
+impl Default for TitleOptions {
+    fn default() -> Self {
+        TitleOptions { template: default_template() }
+    }
+}
+
+/// Sets the terminal window title, this does not add any (visible) segment.
+pub fn process_title<CWD, GIT, T>(cwd: &CWD, git: &GIT, options: &TitleOptions, terminal: &mut T)
+    where CWD: CwdPathPlugin, GIT: GitPlugin, T: TerminalPlugin
+{
+    let mut title = options.template.clone();
+
+    if title.contains("{user}") {
+        title = title.replace("{user}", &env::var("USER").unwrap_or_default());
+    }
+
+    if title.contains("{host}") {
+        let host = hostname().unwrap_or_default();
+        // like bash's \h only use the part up to the first dot
+        let short_host = host.split('.').next().unwrap_or("");
+        title = title.replace("{host}", short_host);
+    }
+
+    if title.contains("{cwd}") || title.contains("{cwd_short}") {
+        let path = cwd.get_current_path().ok().or_else(|| cwd.get_deleted_path());
+        let home = cwd.get_home_path().ok();
+        let (full, short) =
+            match (path, home) {
+                (Some(ref path), Some(ref home)) if path == home => ("~".to_owned(), "~".to_owned()),
+                (Some(path), home) => {
+                    let full =
+                        match home.as_ref().and_then(|home| path.strip_prefix(home).ok()) {
+                            Some(rest) => format!("~/{}", rest.to_string_lossy()),
+                            None => path.to_string_lossy().into_owned()
+                        };
+                    let short = path.file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| full.clone());
+                    (full, short)
+                },
+                (None, _) => ("????".to_owned(), "????".to_owned())
+            };
+        title = title.replace("{cwd}", &full).replace("{cwd_short}", &short);
+    }
+
+    if title.contains("{git_branch}") {
+        let branch =
+            match git.lookup_status() {
+                Ok(info) => info.branch,
+                Err(GitError::NotAvailable) => String::new(),
+                Err(err) => {
+                    terminal.add_error(ERR_SCOPE, &err);
+                    String::new()
+                }
+            };
+        title = title.replace("{git_branch}", &branch);
+    }
+
+    terminal.set_title(&title);
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{RecordingTerminal, FakeCwd, FakeGit};
+
+    #[test]
+    fn expanding_the_template() {
+        let mut terminal = RecordingTerminal::new(80);
+        let options = TitleOptions { template: "{cwd_short} ({cwd}) [{git_branch}]".to_owned() };
+        let cwd = FakeCwd { current: "/home/u/src/prompty".into(), home: Some("/home/u"), ..Default::default() };
+        let git = FakeGit { branch: "main", ..Default::default() };
+        process_title(&cwd, &git, &options, &mut terminal);
+        assert_eq!(terminal.title.as_deref(), Some("prompty (~/src/prompty) [main]"));
+        assert!(terminal.segments.is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    cmp::max,
+    rc::Rc
+};
+
+use crate::{
+    iface::{TerminalPlugin, CwdPathPlugin, GitPlugin, ColumnCountPlugin, ErrorMessage, RenderTarget, RenderKind},
+    config::{self, Config, SegmentOptions},
+    segment::{builtin_registry, Segment, SegmentFactory, MemoizedCwd}
+};
+
+/// Builder for the prompt run loop.
+///
+/// The run loop determines the column count, loads the config,
+/// creates all configured segments, renders them and flushes
+/// the result to stdout.
+pub struct PromptBuilder<TERM, PATH, GIT, COL>
+    where TERM: TerminalPlugin
+{
+    cwd: PATH,
+    git: GIT,
+    columns: COL,
+    config: Option<Config>,
+    exit_status: Option<i32>,
+    target: RenderTarget,
+    prompt_ending: String,
+    segments: Vec<(String, SegmentFactory<TERM>)>,
+    errors: Vec<(&'static str, ErrorMessage)>
+}
+
+impl<TERM, PATH, GIT, COL> PromptBuilder<TERM, PATH, GIT, COL>
+    where TERM: TerminalPlugin,
+          PATH: CwdPathPlugin + 'static,
+          GIT: GitPlugin + 'static,
+          COL: ColumnCountPlugin
+{
+    pub fn new(cwd: PATH, git: GIT, columns: COL) -> Self {
+        PromptBuilder {
+            cwd,
+            git,
+            columns,
+            config: None,
+            exit_status: None,
+            target: RenderTarget::default(),
+            prompt_ending: config::PROMPT_ENDING.to_owned(),
+            segments: Vec::new(),
+            errors: Vec::new()
+        }
+    }
+
+    /// Uses given config instead of loading it from the config file.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Sets the exit status of the last command, as shown by the `exit_status` segment.
+    pub fn exit_status(mut self, exit_status: Option<i32>) -> Self {
+        self.exit_status = exit_status;
+        self
+    }
+
+    /// Sets which prompt is rendered for which shell, by default the main prompt for bash.
+    pub fn target(mut self, target: RenderTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Adds an error which happened before running the prompt, e.g. while loading the config.
+    pub fn error(mut self, scope: &'static str, err: ErrorMessage) -> Self {
+        self.errors.push((scope, err));
+        self
+    }
+
+    pub fn prompt_ending(mut self, prompt_ending: impl Into<String>) -> Self {
+        self.prompt_ending = prompt_ending.into();
+        self
+    }
+
+    /// Adds an additional segment kind, which can be used in the config like the builtin ones.
+    ///
+    /// Segment kinds with the same name as a builtin one replace the builtin one.
+    pub fn segment<F>(mut self, kind: impl Into<String>, factory: F) -> Self
+        where F: Fn(&str, &SegmentOptions) -> Result<Box<dyn Segment<TERM>>, ErrorMessage> + 'static
+    {
+        self.segments.push((kind.into(), Box::new(factory)));
+        self
+    }
+
+    pub fn run(self) {
+        let PromptBuilder {
+            cwd, git, columns, config, exit_status, target, prompt_ending, segments, errors
+        } = self;
+
+        let (columns, delayed_error) =
+            match columns.get_column_count() {
+                Ok(cols) => (cols, None),
+                Err(err) => (config::FALLBACK_COLUMN_COUNT, Some(err))
+            };
+
+        let columns = max(columns, config::MIN_COLUMN_COUNT);
+
+        let (config, config_error) =
+            match config {
+                Some(config) => (config, None),
+                None => Config::load_or_default()
+            };
+
+        let mut terminal = TERM::new(columns, &config.terminal.options(), &target);
+        // the main prompt already shows them
+        if target.kind == RenderKind::Prompt {
+            if let Some(err) = delayed_error {
+                terminal.add_error("columns", &err);
+            }
+            for (scope, err) in errors {
+                terminal.add_error_segment(scope, err.msg());
+            }
+            if let Some(err) = config_error {
+                terminal.add_error_segment("config", err.msg());
+            }
+        }
+
+        // errors are reported by the cwd segment
+        let cwd = Rc::new(MemoizedCwd::new(cwd));
+        if let Ok(path) = cwd.get_current_path() {
+            terminal.set_cwd(&path);
+        }
+
+        let mut registry = builtin_registry(cwd, Rc::new(git), exit_status);
+        for (kind, factory) in segments {
+            registry.register_boxed(kind, factory);
+        }
+
+        for (layout, segment) in registry.create_all(&config, target.kind, &mut terminal) {
+            terminal.begin_segment(&layout);
+            segment.render(&mut terminal);
+        }
+
+        terminal.flush_to_stdout(&prompt_ending);
+    }
+}
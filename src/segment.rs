@@ -0,0 +1,453 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    rc::Rc
+};
+
+use serde::de::DeserializeOwned;
+use toml;
+
+use crate::{
+    iface::{
+        TerminalPlugin, CwdPathPlugin, GitPlugin, GitInfo,
+        ErrorMessage, SegmentLayout, Alignment, RenderKind
+    },
+    error::{GitError, CwdError},
+    config::{Config, SegmentOptions},
+    process_cwd::{process_cwd, CwdOptions},
+    process_git::{process_git, GitOptions},
+    process_exit_status::process_exit_status,
+    process_command::{CommandSegment, CommandOptions},
+    process_script::{ScriptSegment, ScriptOptions},
+    process_title::{process_title, TitleOptions}
+};
+
+const ERR_SCOPE: &str = "config";
+
+/// Key in the segment options which selects the kind of segment to create.
+///
+/// If it is not given the name of the segment is used as kind, this allows
+/// e.g. using the same segment kind multiple times with different options.
+const KIND_KEY: &str = "type";
+
+/// Key in the segment options which sets the priority of the segment, see [`SegmentLayout`].
+const PRIORITY_KEY: &str = "priority";
+
+/// A part of the prompt, rendered into the terminal plugin.
+///
+/// Segments are created by name through a [`SegmentRegistry`].
+pub trait Segment<T: TerminalPlugin> {
+    fn render(&self, terminal: &mut T);
+}
+
+/// Creates a segment from its name (as used in the config) and its options.
+pub type SegmentFactory<T> =
+    Box<dyn Fn(&str, &SegmentOptions) -> Result<Box<dyn Segment<T>>, ErrorMessage>>;
+
+/// Maps segment kinds to factories creating segments of that kind.
+pub struct SegmentRegistry<T: TerminalPlugin> {
+    factories: HashMap<String, SegmentFactory<T>>
+}
+
+impl<T> SegmentRegistry<T>
+    where T: TerminalPlugin
+{
+    pub fn new() -> Self {
+        SegmentRegistry { factories: HashMap::new() }
+    }
+
+    /// Registers a segment kind, replacing any previous registration of the same kind.
+    pub fn register<F>(&mut self, kind: impl Into<String>, factory: F)
+        where F: Fn(&str, &SegmentOptions) -> Result<Box<dyn Segment<T>>, ErrorMessage> + 'static
+    {
+        self.register_boxed(kind, Box::new(factory));
+    }
+
+    pub fn register_boxed(&mut self, kind: impl Into<String>, factory: SegmentFactory<T>) {
+        self.factories.insert(kind.into(), factory);
+    }
+
+    pub fn create(&self, name: &str, options: &SegmentOptions)
+        -> Result<Box<dyn Segment<T>>, ErrorMessage>
+    {
+        let mut options = options.clone();
+        let kind =
+            match options.remove(KIND_KEY) {
+                Some(toml::Value::String(kind)) => kind,
+                Some(other) => {
+                    let msg = format!("segment {}: `{}` must be a string, got: {}", name, KIND_KEY, other);
+                    return Err(ErrorMessage::new(msg));
+                },
+                None => name.to_owned()
+            };
+
+        match self.factories.get(&kind) {
+            Some(factory) => factory(name, &options)
+                .map_err(|err| ErrorMessage::new(format!("segment {}: {}", name, err.msg()))),
+            None => Err(ErrorMessage::new(format!("unknown segment kind: {}", kind)))
+        }
+    }
+
+    /// Creates all segments listed in the config for given kind of prompt,
+    /// in the order they are listed in.
+    ///
+    /// Segments which can not be created are skipped and reported as error
+    /// segments to the terminal.
+    pub fn create_all(&self, config: &Config, kind: RenderKind, terminal: &mut T)
+        -> Vec<(SegmentLayout, Box<dyn Segment<T>>)>
+    {
+        let (left, right) =
+            match kind {
+                RenderKind::Prompt => (&config.segments[..], &config.right_segments[..]),
+                RenderKind::Right => (&[][..], &config.rprompt_segments[..]),
+                RenderKind::Transient | RenderKind::Continuation | RenderKind::Select => (&[][..], &[][..])
+            };
+        let left = left.iter().map(|name| (Alignment::Left, name));
+        let right = right.iter().map(|name| (Alignment::Right, name));
+
+        let mut segments = Vec::with_capacity(left.len() + right.len());
+        for (align, name) in left.chain(right) {
+            let mut options = config.segment_options(name);
+            let created = take_priority(name, &mut options)
+                .and_then(|priority| {
+                    let segment = self.create(name, &options)?;
+                    Ok((SegmentLayout { align, priority }, segment))
+                });
+            match created {
+                Ok(segment) => segments.push(segment),
+                Err(err) => terminal.add_error_segment(ERR_SCOPE, err.msg())
+            }
+        }
+        segments
+    }
+}
+
+impl<T> Default for SegmentRegistry<T>
+    where T: TerminalPlugin
+{
+    fn default() -> Self {
+        SegmentRegistry::new()
+    }
+}
+
+/// Creates a registry containing all segments provided by prompty.
+pub fn builtin_registry<T, PATH, GIT>(cwd: Rc<PATH>, git: Rc<GIT>, exit_status: Option<i32>)
+    -> SegmentRegistry<T>
+    where T: TerminalPlugin, PATH: CwdPathPlugin + 'static, GIT: GitPlugin + 'static
+{
+    let mut registry = SegmentRegistry::new();
+    // the git, title and script segments all use the status
+    let git = Rc::new(MemoizedGit { git, status: RefCell::new(None) });
+    let (title_cwd, title_git) = (cwd.clone(), git.clone());
+    let script_cwd = cwd.clone();
+    registry.register("cwd", move |_, options| {
+        let options = parse_options::<CwdOptions>(options)?;
+        Ok(Box::new(CwdSegment { plugin: cwd.clone(), options }))
+    });
+    let script_git = git.clone();
+    registry.register("git", move |_, options| {
+        let options = parse_options::<GitOptions>(options)?;
+        Ok(Box::new(GitSegment { plugin: git.clone(), options }))
+    });
+    registry.register("title", move |_, options| {
+        let options = parse_options::<TitleOptions>(options)?;
+        Ok(Box::new(TitleSegment { cwd: title_cwd.clone(), git: title_git.clone(), options }))
+    });
+    registry.register("exit_status", move |_, options| {
+        let options = parse_options::<ExitStatusOptions>(options)?;
+        Ok(Box::new(ExitStatusSegment { status: exit_status, options }))
+    });
+    registry.register("command", |name, options| {
+        let options = parse_options::<CommandOptions>(options)?;
+        Ok(Box::new(CommandSegment::new(name, options)))
+    });
+    registry.register("script", move |name, options| {
+        let options = parse_options::<ScriptOptions>(options)?;
+        Ok(Box::new(ScriptSegment::new(name, options, script_cwd.clone(), script_git.clone())?))
+    });
+    registry
+}
+
+/// Cwd plugin which looks up the current path only once per prompt, as the
+/// terminal (e.g. for OSC 7) and several segments need it.
+pub(crate) struct MemoizedCwd<PATH> {
+    cwd: PATH,
+    current: RefCell<Option<Result<PathBuf, CwdError>>>
+}
+
+impl<PATH> MemoizedCwd<PATH> {
+    pub(crate) fn new(cwd: PATH) -> Self {
+        MemoizedCwd { cwd, current: RefCell::new(None) }
+    }
+}
+
+impl<PATH> CwdPathPlugin for MemoizedCwd<PATH>
+    where PATH: CwdPathPlugin
+{
+    fn get_current_path(&self) -> Result<PathBuf, CwdError> {
+        self.current.borrow_mut()
+            .get_or_insert_with(|| self.cwd.get_current_path())
+            .clone()
+    }
+
+    fn get_physical_path(&self, path: &Path) -> Result<PathBuf, CwdError> {
+        self.cwd.get_physical_path(path)
+    }
+
+    fn get_deleted_path(&self) -> Option<PathBuf> {
+        self.cwd.get_deleted_path()
+    }
+
+    fn is_writable(&self, path: &Path) -> bool {
+        self.cwd.is_writable(path)
+    }
+
+    fn get_top_path(&self) -> Result<PathBuf, CwdError> {
+        self.cwd.get_top_path()
+    }
+
+    fn get_home_path(&self) -> Result<PathBuf, CwdError> {
+        self.cwd.get_home_path()
+    }
+
+    fn get_vcs_root(&self, path: &Path) -> Result<PathBuf, CwdError> {
+        self.cwd.get_vcs_root(path)
+    }
+
+    fn get_sub_dir_names(&self, path: &Path) -> Result<Vec<OsString>, CwdError> {
+        self.cwd.get_sub_dir_names(path)
+    }
+}
+
+/// Git plugin which looks up the status only once per prompt.
+struct MemoizedGit<GIT> {
+    git: Rc<GIT>,
+    status: RefCell<Option<Result<GitInfo, GitError>>>
+}
+
+impl<GIT> GitPlugin for MemoizedGit<GIT>
+    where GIT: GitPlugin
+{
+    fn lookup_status(&self) -> Result<GitInfo, GitError> {
+        self.status.borrow_mut()
+            .get_or_insert_with(|| self.git.lookup_status())
+            .clone()
+    }
+
+    fn lookup_remote_url(&self, remote: &str) -> Result<String, GitError> {
+        self.git.lookup_remote_url(remote)
+    }
+}
+
+/// Removes the priority from the segment options, it defaults to 0.
+fn take_priority(name: &str, options: &mut SegmentOptions) -> Result<i64, ErrorMessage> {
+    match options.remove(PRIORITY_KEY) {
+        Some(toml::Value::Integer(priority)) => Ok(priority),
+        Some(other) => {
+            let msg = format!("segment {}: `{}` must be an integer, got: {}", name, PRIORITY_KEY, other);
+            Err(ErrorMessage::new(msg))
+        },
+        None => Ok(0)
+    }
+}
+
+/// Parses segment options into a options type.
+pub fn parse_options<O>(options: &SegmentOptions) -> Result<O, ErrorMessage>
+    where O: DeserializeOwned
+{
+    toml::Value::Table(options.clone())
+        .try_into()
+        .map_err(|err| ErrorMessage::new(format!("invalid options: {}", err)))
+}
+
+struct CwdSegment<PATH> {
+    plugin: Rc<PATH>,
+    options: CwdOptions
+}
+
+impl<T, PATH> Segment<T> for CwdSegment<PATH>
+    where T: TerminalPlugin, PATH: CwdPathPlugin
+{
+    fn render(&self, terminal: &mut T) {
+        process_cwd(&*self.plugin, &self.options, terminal);
+    }
+}
+
+struct GitSegment<GIT> {
+    plugin: Rc<GIT>,
+    options: GitOptions
+}
+
+impl<T, GIT> Segment<T> for GitSegment<GIT>
+    where T: TerminalPlugin, GIT: GitPlugin
+{
+    fn render(&self, terminal: &mut T) {
+        process_git(&*self.plugin, &self.options, terminal);
+    }
+}
+
+struct TitleSegment<PATH, GIT> {
+    cwd: Rc<PATH>,
+    git: Rc<GIT>,
+    options: TitleOptions
+}
+
+impl<T, PATH, GIT> Segment<T> for TitleSegment<PATH, GIT>
+    where T: TerminalPlugin, PATH: CwdPathPlugin, GIT: GitPlugin
+{
+    fn render(&self, terminal: &mut T) {
+        process_title(&*self.cwd, &*self.git, &self.options, terminal);
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ExitStatusOptions {
+    /// Also show the exit status if the last command succeeded.
+    show_success: bool
+}
+
+struct ExitStatusSegment {
+    status: Option<i32>,
+    options: ExitStatusOptions
+}
+
+impl<T> Segment<T> for ExitStatusSegment
+    where T: TerminalPlugin
+{
+    fn render(&self, terminal: &mut T) {
+        if let Some(status) = self.status {
+            process_exit_status(terminal, status, self.options.show_success);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{RecordingTerminal, FakeCwd, FakeGit};
+
+    struct Static(&'static str);
+
+    impl Segment<RecordingTerminal> for Static {
+        fn render(&self, terminal: &mut RecordingTerminal) {
+            terminal.add_text_segment(self.0, crate::iface::FormatLike::Text);
+        }
+    }
+
+    fn registry() -> SegmentRegistry<RecordingTerminal> {
+        let mut registry = SegmentRegistry::new();
+        registry.register("a", |_, _| Ok(Box::new(Static("a"))));
+        registry.register("b", |_, _| Ok(Box::new(Static("b"))));
+        registry
+    }
+
+    fn render_all(config: &str) -> RecordingTerminal {
+        render_kind(config, RenderKind::Prompt)
+    }
+
+    fn render_kind(config: &str, kind: RenderKind) -> RecordingTerminal {
+        let config = Config::parse(config).unwrap();
+        let mut terminal = RecordingTerminal::new(80);
+        for (layout, segment) in registry().create_all(&config, kind, &mut terminal) {
+            terminal.begin_segment(&layout);
+            segment.render(&mut terminal);
+        }
+        terminal
+    }
+
+    #[test]
+    fn segments_are_rendered_in_config_order() {
+        let terminal = render_all(r#"segments = ["b", "a", "b"]"#);
+        assert_eq!(terminal.texts(), vec!["b", "a", "b"]);
+        assert!(terminal.errors.is_empty());
+    }
+
+    #[test]
+    fn right_segments_are_rendered_after_left_ones() {
+        let terminal = render_all(r#"
+            segments = ["a"]
+            right_segments = ["b"]
+        "#);
+        assert_eq!(terminal.texts(), vec!["a", "b"]);
+        assert_eq!(terminal.alignments, vec![Alignment::Left, Alignment::Right]);
+    }
+
+    #[test]
+    fn right_prompt_only_renders_rprompt_segments() {
+        let terminal = render_kind(r#"
+            segments = ["a"]
+            right_segments = ["a"]
+            rprompt_segments = ["b"]
+        "#, RenderKind::Right);
+        assert_eq!(terminal.texts(), vec!["b"]);
+    }
+
+    #[test]
+    fn invalid_priorities_are_reported() {
+        let terminal = render_all(r#"
+            segments = ["a", "b"]
+            [segment.a]
+            priority = -1
+            [segment.b]
+            priority = "high"
+        "#);
+        assert_eq!(terminal.texts(), vec!["a"]);
+        assert_eq!(terminal.errors, vec![
+            ("config", "segment b: `priority` must be an integer, got: \"high\"".to_owned())
+        ]);
+    }
+
+    #[test]
+    fn segments_can_be_aliased_using_type() {
+        let terminal = render_all(r#"
+            segments = ["other_a", "b"]
+            [segment.other_a]
+            type = "a"
+        "#);
+        assert_eq!(terminal.texts(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn unknown_segments_are_reported() {
+        let terminal = render_all(r#"segments = ["a", "nope"]"#);
+        assert_eq!(terminal.texts(), vec!["a"]);
+        assert_eq!(terminal.errors, vec![("config", "unknown segment kind: nope".to_owned())]);
+    }
+
+    #[test]
+    fn git_status_is_looked_up_once() {
+        let git = Rc::new(FakeGit { branch: "main", ..Default::default() });
+        let registry = builtin_registry::<RecordingTerminal, _, _>(Rc::new(FakeCwd::default()), git.clone(), None);
+        let config = Config::parse(r#"
+            segments = ["git", "title"]
+            [segment.title]
+            template = "{git_branch}"
+        "#).unwrap();
+        let mut terminal = RecordingTerminal::new(80);
+        for (layout, segment) in registry.create_all(&config, RenderKind::Prompt, &mut terminal) {
+            terminal.begin_segment(&layout);
+            segment.render(&mut terminal);
+        }
+        assert_eq!(terminal.title.as_deref(), Some("main"));
+        assert_eq!(git.lookups.get(), 1);
+    }
+
+    #[test]
+    fn invalid_options_are_reported() {
+        let cwd = Rc::new(crate::plugin_impl::CwdPath);
+        let git = Rc::new(crate::plugin_impl::Git::default());
+        let registry = builtin_registry::<RecordingTerminal, _, _>(cwd, git, None);
+        let config = Config::parse(r#"
+            segments = ["exit_status"]
+            [segment.exit_status]
+            show_succes = true
+        "#).unwrap();
+        let mut terminal = RecordingTerminal::new(80);
+        assert!(registry.create_all(&config, RenderKind::Prompt, &mut terminal).is_empty());
+        assert_eq!(terminal.errors.len(), 1);
+    }
+}
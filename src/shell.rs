@@ -0,0 +1,295 @@
+//! Shell code which installs prompty as prompt, as emitted by `--bash-setup` and `--zsh-setup`.
+use std::{
+    cmp::max,
+    path::Path
+};
+
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    iface::ErrorMessage,
+    config::Config,
+    jump
+};
+
+/// Returns bash code setting up prompty as prompt and adding the jump function.
+///
+/// The code has to be evaluated quoted (i.e. `eval "$(prompty --bash-setup)"`), as
+/// it's neither safe against word splitting nor against glob expansion.
+/// If the jump function can't be created the rest of the code is still returned,
+/// together with the error.
+pub fn bash_setup(config: &Config, exec: &Path) -> (String, Option<ErrorMessage>) {
+    let mut lines = Vec::new();
+    let mut error = None;
+
+    match jump::bash_function(&config.jump, exec) {
+        Ok(function) => lines.push(function),
+        Err(err) => error = Some(err)
+    }
+
+    let exec = exec.display();
+    let (status, prompt, mut ps0) =
+        if config.terminal.transient_prompt {
+            // the prompt is rendered in `PROMPT_COMMAND` so that `PS0` knows what to replace
+            lines.push(format!(
+                concat!(
+                    r#"_prompty_prompt_command() {{ __prompty_status=$?; __prompty_history=$(HISTTIMEFORMAT= history 1); "#,
+                    r#"__prompty_ps1=$("{exec}" $COLUMNS $__prompty_status); }};"#
+                ),
+                exec=exec
+            ));
+            lines.push(r#"PROMPT_COMMAND="_prompty_prompt_command;$PROMPT_COMMAND";"#.to_owned());
+            // the previous history entry tells whether the command was added to the history
+            let transient = format!(
+                concat!(
+                    r#"$("{exec}" render --kind transient --previous-prompt "$__prompty_ps1" --continuation-prompt "${{PS2@P}}" "#,
+                    r#"--previous-history-entry "$__prompty_history" --history-entry "$(HISTTIMEFORMAT= history 1)" $COLUMNS)"#
+                ),
+                exec=exec
+            );
+            ("$__prompty_status", "${__prompty_ps1}".to_owned(), transient)
+        } else {
+            ("$?", format!(r#"$("{exec}" $COLUMNS $?)"#, exec=exec), String::new())
+        };
+
+    if config.terminal.semantic_prompt {
+        // prompty marks the start (A) and end (B) of the prompt, the shell the
+        // start of the command output (C) and the end of the command (D)
+        ps0.push_str(r#"\e]133;C\a"#);
+        lines.push(format!(r#"PS1='\[\e]133;D;{}\a\]{}';"#, status, prompt));
+    } else {
+        lines.push(format!("PS1='{}';", prompt));
+    }
+    if !ps0.is_empty() {
+        lines.push(format!("PS0='{}';", ps0));
+    }
+    lines.push(format!(r#"PS2='$("{exec}" render --kind continuation $COLUMNS)';"#, exec=exec));
+    if config.terminal.select_prompt {
+        // `PS3` isn't expanded, so it's rendered once
+        lines.push(format!(r#"PS3="$("{exec}" render --kind select $COLUMNS)";"#, exec=exec));
+    }
+    if config.terminal.xtrace_prompt {
+        // the first char is repeated for each level of indirection
+        lines.push("PS4='+ ${BASH_SOURCE:-bash}:${LINENO}: ';".to_owned());
+    }
+
+    (lines.join("\n"), error)
+}
+
+/// Returns zsh code setting up prompty as `PROMPT` and `RPROMPT`.
+///
+/// Like [`bash_setup`] the code has to be evaluated quoted. The jump function
+/// uses bash completion and is therefore not set up.
+pub fn zsh_setup(config: &Config, exec: &Path) -> (String, Option<ErrorMessage>) {
+    let render = format!(r#""{exec}" render --shell zsh"#, exec=exec.display());
+    let mut set_prompt = format!(r#"PROMPT='$({} $COLUMNS $__prompty_status)';"#, render);
+    if !config.rprompt_segments.is_empty() {
+        set_prompt.push_str(&format!(r#" RPROMPT='$({} --kind right $COLUMNS $__prompty_status)';"#, render));
+    }
+
+    let mut lines = vec!["setopt prompt_subst;".to_owned()];
+    if config.terminal.transient_prompt {
+        // the prompt is replaced before running the command and restored before the next prompt
+        let mut clear_prompt = format!(r#"PROMPT='$({} --kind transient $COLUMNS)';"#, render);
+        if !config.rprompt_segments.is_empty() {
+            clear_prompt.push_str(" RPROMPT='';");
+        }
+        lines.push(format!("_prompty_set_prompt() {{ {} }};", set_prompt));
+        lines.push(format!("_prompty_line_finish() {{ {} zle reset-prompt; }};", clear_prompt));
+        // unlike `zle -N zle-line-finish` this keeps line-finish widgets of other plugins
+        lines.push("autoload -Uz add-zle-hook-widget;".to_owned());
+        lines.push("add-zle-hook-widget line-finish _prompty_line_finish;".to_owned());
+        lines.push("_prompty_precmd() { __prompty_status=$?; _prompty_set_prompt; };".to_owned());
+    } else {
+        // the command substitution in `PROMPT` would overwrite `$?` for `RPROMPT`
+        lines.push("_prompty_precmd() { __prompty_status=$?; };".to_owned());
+    }
+    lines.push("precmd_functions+=(_prompty_precmd);".to_owned());
+
+    if config.terminal.semantic_prompt {
+        lines.push(r#"_prompty_mark_end() { printf '\e]133;D;%s\a' "$__prompty_status"; };"#.to_owned());
+        lines.push("precmd_functions+=(_prompty_mark_end);".to_owned());
+        lines.push(r#"_prompty_mark_output() { printf '\e]133;C\a'; };"#.to_owned());
+        lines.push("preexec_functions+=(_prompty_mark_output);".to_owned());
+    }
+
+    if !config.terminal.transient_prompt {
+        lines.push(set_prompt);
+    }
+    lines.push(format!(r#"PROMPT2='$({} --kind continuation $COLUMNS)';"#, render));
+    if config.terminal.select_prompt {
+        lines.push(format!(r#"PROMPT3='$({} --kind select $COLUMNS)';"#, render));
+    }
+
+    (lines.join("\n"), None)
+}
+
+/// Returns the escape sequence which moves the cursor from the line after the
+/// entered `command` back to the start of `prompt` and clears everything after it.
+///
+/// The lines of the command after the first one start with the `continuation` prompt.
+/// Used for the transient prompt of shells which can't redraw the prompt themselves.
+pub fn transient_redraw(prompt: &str, continuation: &str, command: &str, columns: usize) -> String {
+    let prompt = strip_non_printing(prompt);
+    let continuation_width = strip_non_printing(continuation).width();
+    let mut prompt_lines = prompt.split('\n').collect::<Vec<_>>();
+    let last_prompt_line = prompt_lines.pop().unwrap_or_default();
+    let mut command_lines = command.split('\n');
+    let first_command_line = command_lines.next().unwrap_or_default();
+
+    // `$COLUMNS` can be 0, e.g. if the terminal size is unknown
+    let columns = max(1, columns);
+    let rows_of = |width: usize| max(1, width.div_ceil(columns));
+    let rows = prompt_lines.iter().map(|line| rows_of(line.width())).sum::<usize>()
+        + rows_of(last_prompt_line.width() + first_command_line.width())
+        + command_lines.map(|line| rows_of(continuation_width + line.width())).sum::<usize>();
+
+    format!("\x1b[{}A\r\x1b[J", rows)
+}
+
+/// Returns if the history `entry` printed for a command is a new one, given the `previous`
+/// entry printed before it was entered.
+///
+/// E.g. with `HISTCONTROL=ignorespace` commands starting with a space are not added to the
+/// history, so `history 1` returns the previous command instead.
+pub fn is_new_history_entry(previous: &str, entry: &str) -> bool {
+    history_number(previous) != history_number(entry)
+}
+
+fn history_number(entry: &str) -> &str {
+    let entry = entry.trim_start();
+    let end = entry.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(entry.len());
+    &entry[..end]
+}
+
+/// Returns the command of an entry printed by bash's `history` (with an empty `HISTTIMEFORMAT`).
+pub fn command_from_history(entry: &str) -> &str {
+    // e.g. `  123  ls`, or `  123* ls` if the entry was modified
+    let rest = entry.trim_start().trim_start_matches(|ch: char| ch.is_ascii_digit());
+    let rest = rest.strip_prefix(['*', ' ']).unwrap_or(rest);
+    rest.strip_prefix(' ').unwrap_or(rest)
+}
+
+/// Removes the `\x01`..`\x02` delimited parts which don't take up any space.
+fn strip_non_printing(prompt: &str) -> String {
+    let mut out = String::with_capacity(prompt.len());
+    let mut printing = true;
+    for ch in prompt.chars() {
+        match ch {
+            '\x01' => printing = false,
+            '\x02' => printing = true,
+            ch if printing => out.push(ch),
+            _ => {}
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bash_setup_with_semantic_prompt() {
+        let mut config = Config::default();
+        config.terminal.semantic_prompt = true;
+        let (code, err) = bash_setup(&config, Path::new("/bin/prompty"));
+        assert!(err.is_none());
+        assert!(code.contains(r#"PS1='\[\e]133;D;$?\a\]$("/bin/prompty" $COLUMNS $?)';"#));
+        assert!(code.contains(r#"PS0='\e]133;C\a';"#));
+    }
+
+    #[test]
+    fn zsh_setup_sets_prompt_and_rprompt() {
+        let config = Config { rprompt_segments: vec!["git".to_owned()], ..Default::default() };
+        let (code, err) = zsh_setup(&config, Path::new("/bin/prompty"));
+        assert!(err.is_none());
+        assert!(code.contains(r#"PROMPT='$("/bin/prompty" render --shell zsh $COLUMNS $__prompty_status)';"#));
+        assert!(code.contains(
+            r#"RPROMPT='$("/bin/prompty" render --shell zsh --kind right $COLUMNS $__prompty_status)';"#
+        ));
+    }
+
+    #[test]
+    fn bash_setup_sets_secondary_prompts() {
+        let mut config = Config::default();
+        let (code, _) = bash_setup(&config, Path::new("/bin/prompty"));
+        assert!(code.contains(r#"PS2='$("/bin/prompty" render --kind continuation $COLUMNS)';"#));
+        assert!(!code.contains("PS3=") && !code.contains("PS4="));
+
+        config.terminal.select_prompt = true;
+        config.terminal.xtrace_prompt = true;
+        let (code, _) = bash_setup(&config, Path::new("/bin/prompty"));
+        assert!(code.contains(r#"PS3="$("/bin/prompty" render --kind select $COLUMNS)";"#));
+        assert!(code.contains("PS4='+ ${BASH_SOURCE:-bash}:${LINENO}: ';"));
+    }
+
+    #[test]
+    fn bash_setup_with_transient_prompt() {
+        let mut config = Config::default();
+        config.terminal.transient_prompt = true;
+        let (code, _) = bash_setup(&config, Path::new("/bin/prompty"));
+        assert!(code.contains(r#"PROMPT_COMMAND="_prompty_prompt_command;$PROMPT_COMMAND";"#));
+        // the status has to be saved before visits are recorded
+        assert!(code.find("_prompty_prompt_command;$PROMPT").unwrap() > code.find("_prompty_record_visit;$PROMPT").unwrap());
+        assert!(code.contains("PS1='${__prompty_ps1}';"));
+        assert!(code.contains(r#"PS0='$("/bin/prompty" render --kind transient --previous-prompt"#));
+    }
+
+    #[test]
+    fn zsh_setup_with_transient_prompt() {
+        let mut config = Config::default();
+        config.terminal.transient_prompt = true;
+        let (code, _) = zsh_setup(&config, Path::new("/bin/prompty"));
+        assert!(code.contains(concat!(
+            r#"_prompty_line_finish() { PROMPT='$("/bin/prompty" render --shell zsh --kind transient $COLUMNS)';"#,
+            " zle reset-prompt; };"
+        )));
+        assert!(code.contains("add-zle-hook-widget line-finish _prompty_line_finish;"));
+        assert!(!code.contains("\nPROMPT="));
+    }
+
+    #[test]
+    fn transient_redraw_counts_wrapped_lines() {
+        let prompt = "\x01\x1b[31m\x02╔⟦~⟧══\n╚☛ ";
+        assert_eq!(transient_redraw(prompt, "║  ", "ls", 10), "\x1b[2A\r\x1b[J");
+        // `╚☛ ` + 8 chars wrap into a second line, then another line
+        assert_eq!(transient_redraw(prompt, "║  ", "ls -la /x\nfoo", 10), "\x1b[4A\r\x1b[J");
+        assert_eq!(transient_redraw("☛ ", "", "ls", 0), "\x1b[4A\r\x1b[J");
+    }
+
+    #[test]
+    fn transient_redraw_counts_continuation_prompt() {
+        // the second line only wraps because of the `║  ` in front of it
+        let continuation = "\x01\x1b[31m\x02║  \x01\x1b[0m\x02";
+        assert_eq!(transient_redraw("☛ ", continuation, "ls\n12345678", 10), "\x1b[3A\r\x1b[J");
+        assert_eq!(transient_redraw("☛ ", "", "ls\n12345678", 10), "\x1b[2A\r\x1b[J");
+    }
+
+    #[test]
+    fn ignored_history_entries() {
+        assert!(is_new_history_entry("  122  cd", "  123  ls"));
+        assert!(!is_new_history_entry("  122  cd", "  122  cd"));
+        assert!(!is_new_history_entry("", ""));
+    }
+
+    #[test]
+    fn commands_from_history() {
+        assert_eq!(command_from_history("  123  ls -la"), "ls -la");
+        assert_eq!(command_from_history("  124* cd  x"), "cd  x");
+        assert_eq!(command_from_history("1001  echo\nfoo"), "echo\nfoo");
+    }
+
+    #[test]
+    fn invalid_jump_function_name() {
+        let mut config = Config::default();
+        config.jump.function_name = "g;rm".to_owned();
+        let (code, err) = bash_setup(&config, Path::new("/bin/prompty"));
+        assert!(err.is_some());
+        assert_eq!(code, concat!(
+            r#"PS1='$("/bin/prompty" $COLUMNS $?)';"#, "\n",
+            r#"PS2='$("/bin/prompty" render --kind continuation $COLUMNS)';"#
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    cell::Cell,
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf}
+};
+
+use crate::{
+    iface::{
+        TerminalPlugin, CwdPathPlugin, GitPlugin, GitInfo,
+        FormatLike, SegmentLayout, Alignment, RenderTarget, TerminalOptions
+    },
+    error::{CwdError, GitError}
+};
+
+/// Terminal which just records everything added to it.
+#[derive(Debug, Default)]
+pub struct RecordingTerminal {
+    pub columns: usize,
+    pub segments: Vec<Vec<(String, FormatLike)>>,
+    /// The alignment of each entry in `segments`.
+    pub alignments: Vec<Alignment>,
+    pub align: Alignment,
+    pub errors: Vec<(&'static str, String)>,
+    pub cwd: Option<PathBuf>,
+    pub links: Vec<String>,
+    pub title: Option<String>
+}
+
+impl RecordingTerminal {
+    pub fn new(columns: usize) -> Self {
+        RecordingTerminal { columns, ..Default::default() }
+    }
+
+    /// Returns the text of all segments, with extensions joined to the segment they extend.
+    pub fn texts(&self) -> Vec<String> {
+        self.segments.iter()
+            .map(|group| group.iter().map(|(text, _)| text.as_str()).collect())
+            .collect()
+    }
+}
+
+impl TerminalPlugin for RecordingTerminal {
+    fn new(columns: usize, _options: &TerminalOptions, _target: &RenderTarget) -> Self {
+        RecordingTerminal::new(columns)
+    }
+
+    fn column_count(&self) -> usize {
+        self.columns
+    }
+
+    fn begin_segment(&mut self, layout: &SegmentLayout) {
+        self.align = layout.align;
+    }
+
+    fn add_text_segment(&mut self, text: &str, fmt_args: FormatLike) {
+        self.add_text_segment_parts(&[(text, fmt_args)]);
+    }
+
+    fn add_text_segment_parts(&mut self, parts: &[(&str, FormatLike)]) {
+        self.segments.push(parts.iter().map(|(text, fmt)| (text.to_string(), *fmt)).collect());
+        self.alignments.push(self.align);
+    }
+
+    fn extend_previous_segment(&mut self, text: &str, fmt_args: FormatLike) {
+        match self.segments.last_mut() {
+            Some(last) => last.push((text.to_owned(), fmt_args)),
+            None => self.add_text_segment(text, fmt_args)
+        }
+    }
+
+    fn add_error_segment(&mut self, scope: &'static str, msg: &str) {
+        self.errors.push((scope, msg.to_owned()));
+    }
+
+    fn link_previous_segment(&mut self, url: &str) {
+        self.links.push(url.to_owned());
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = Some(title.to_owned());
+    }
+
+    fn set_cwd(&mut self, cwd: &Path) {
+        self.cwd = Some(cwd.to_owned());
+    }
+
+    fn flush_to_stdout(&self, _prompt_ending: &str) {}
+}
+
+/// Cwd plugin for a made up directory tree, paths starting with `/read-only` are not writable.
+#[derive(Debug, Default)]
+pub struct FakeCwd {
+    pub current: PathBuf,
+    pub vcs_root: Option<&'static str>,
+    pub home: Option<&'static str>,
+    pub physical: Option<&'static str>,
+    pub deleted: bool
+}
+
+fn env_path(path: Option<&'static str>) -> Result<PathBuf, CwdError> {
+    path.map(PathBuf::from).ok_or(CwdError::NotAvailable)
+}
+
+impl CwdPathPlugin for FakeCwd {
+    fn get_current_path(&self) -> Result<PathBuf, CwdError> {
+        if self.deleted {
+            return Err(CwdError::Cwd(io::ErrorKind::NotFound.into()));
+        }
+        Ok(self.current.clone())
+    }
+
+    fn get_physical_path(&self, path: &Path) -> Result<PathBuf, CwdError> {
+        Ok(self.physical.map(PathBuf::from).unwrap_or_else(|| path.to_owned()))
+    }
+
+    fn get_deleted_path(&self) -> Option<PathBuf> {
+        if self.deleted { Some(self.current.clone()) } else { None }
+    }
+
+    fn is_writable(&self, path: &Path) -> bool {
+        !path.starts_with("/read-only")
+    }
+
+    fn get_top_path(&self) -> Result<PathBuf, CwdError> {
+        Err(CwdError::NotAvailable)
+    }
+
+    fn get_home_path(&self) -> Result<PathBuf, CwdError> {
+        env_path(self.home)
+    }
+
+    fn get_vcs_root(&self, _path: &Path) -> Result<PathBuf, CwdError> {
+        env_path(self.vcs_root)
+    }
+
+    fn get_sub_dir_names(&self, _path: &Path) -> Result<Vec<OsString>, CwdError> {
+        Ok(Vec::new())
+    }
+}
+
+/// Git plugin for a repository on `branch`, all remotes have the `remote_url` (if any).
+#[derive(Debug, Default)]
+pub struct FakeGit {
+    pub branch: &'static str,
+    pub has_untracked_files: bool,
+    pub remote_url: Option<&'static str>,
+    /// How often the status was looked up.
+    pub lookups: Cell<usize>
+}
+
+impl GitPlugin for FakeGit {
+    fn lookup_status(&self) -> Result<GitInfo, GitError> {
+        self.lookups.set(self.lookups.get() + 1);
+        Ok(GitInfo {
+            branch: self.branch.to_owned(),
+            has_untracked_files: self.has_untracked_files,
+            has_unstaged_files: false,
+            has_staged_files: false
+        })
+    }
+
+    fn lookup_remote_url(&self, _remote: &str) -> Result<String, GitError> {
+        self.remote_url.map(str::to_owned).ok_or(GitError::NotAvailable)
+    }
+}
@@ -0,0 +1,39 @@
+//! Helpers for building urls, e.g. for OSC 7 and OSC 8 sequences.
+use std::{
+    os::unix::ffi::OsStrExt,
+    path::Path
+};
+
+use crate::plugin_impl::hostname;
+
+/// Returns the `file://` url of given path, including the host name.
+pub fn file_url(path: &Path) -> String {
+    let host = hostname().unwrap_or_default();
+    format!("file://{}{}", encode(host.as_bytes(), b""), encode(path.as_os_str().as_bytes(), b"/"))
+}
+
+/// Percent encodes all bytes except unreserved chars and the bytes in `keep`.
+pub fn encode(bytes: &[u8], keep: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(byte as char),
+            byte if keep.contains(&byte) => out.push(byte as char),
+            byte => out.push_str(&format!("%{:02X}", byte))
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn percent_encoding() {
+        assert_eq!(encode("/home/u/my dir/ä".as_bytes(), b"/"), "/home/u/my%20dir/%C3%A4");
+        assert_eq!(encode(b"/d\xFFta", b"/"), "/d%FFta");
+        assert_eq!(encode(b"host name/", b""), "host%20name%2F");
+    }
+}